@@ -0,0 +1,122 @@
+// Numbered notes attached to lines. Notes either point at a line directly
+// (`--annotate 14="text"`) or at a callout marker left in the code, AsciiDoc
+// style (`let x = foo(); // <1>` + `--annotate "<1>=text"`).
+
+use std::collections::HashMap;
+
+use regex::Regex;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Note {
+    Line(usize),
+    Callout(usize),
+}
+
+
+#[derive(Debug, Default)]
+pub struct Annotations {
+    // line number -> badge numbers to show on that line
+    pub badges: HashMap<usize, Vec<usize>>,
+    // (badge number, text) in the order they should be listed
+    pub notes: Vec<(usize, String)>,
+}
+
+
+// `14=allocates here` or `<1>=allocates here`
+pub fn parse(note: &str) -> Option<(Note, String)> {
+    let mut parts = note.splitn(2, '=');
+    let key = parts.next()?.trim();
+    let text = parts.next()?.trim();
+
+    let note = if key.starts_with('<') && key.ends_with('>') {
+        Note::Callout(key[1..key.len() - 1].parse().ok()?)
+    } else {
+        Note::Line(key.parse().ok()?)
+    };
+
+    Some((note, String::from(text)))
+}
+
+
+// Pulls trailing `<N>` markers (and the comment holding them, if that's all
+// the comment was) out of the code. Returns the cleaned up text and the
+// callout number found on each line, by 0-based line index. A marker has to
+// be in a comment, so code like `Foo<3>` is left alone.
+pub fn strip_callouts(text: &str) -> (String, HashMap<usize, usize>) {
+    let marker = Regex::new(
+        r"^(.*?)\s*((?://|#|--|;|/\*|<!--)\s*)?<(\d+)>\s*(?:\*/|-->)?\s*$"
+    ).unwrap();
+
+    // a comment the marker comes at the end of, `// allocates <1>`
+    let comment = Regex::new(r"//|#|--|/\*|<!--").unwrap();
+
+    let mut found = HashMap::new();
+    let mut lines = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let callout = marker.captures(line)
+            .filter(|c| c.get(2).is_some() || comment.is_match(&c[1]))
+            .and_then(|c| c[3].parse().ok().map(|n| (n, String::from(&c[1]))));
+
+        match callout {
+            Some((n, code)) => {
+                found.insert(i, n);
+                lines.push(code);
+            }
+            None => lines.push(String::from(line)),
+        }
+    }
+
+    (lines.join("\n"), found)
+}
+
+
+// Works out which badge numbers go where. Callouts keep the number written in
+// the code, line notes are numbered after them in line order.
+pub fn resolve(notes: &[(Note, String)],
+               callouts: &HashMap<usize, usize>,
+               first_line: usize) -> Annotations {
+
+    let mut out = Annotations::default();
+
+    let mut highest = callouts.values().cloned().max().unwrap_or(0);
+
+    for (note, _) in notes {
+        if let Note::Callout(n) = *note {
+            if n > highest { highest = n; }
+        }
+    }
+
+    for (&i, &n) in callouts {
+        out.badges.entry(first_line + i).or_insert_with(Vec::new).push(n);
+    }
+
+    let mut by_line = notes.iter()
+        .filter_map(|(note, text)| match *note {
+            Note::Line(line) => Some((line, text)),
+            Note::Callout(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    by_line.sort_by_key(|&(line, _)| line);
+
+    for (line, text) in by_line {
+        highest += 1;
+        out.badges.entry(line).or_insert_with(Vec::new).push(highest);
+        out.notes.push((highest, text.clone()));
+    }
+
+    for (note, text) in notes {
+        if let Note::Callout(n) = *note {
+            out.notes.push((n, text.clone()));
+        }
+    }
+
+    for badges in out.badges.values_mut() {
+        badges.sort();
+    }
+
+    out.notes.sort_by_key(|&(n, _)| n);
+    out
+}
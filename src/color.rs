@@ -10,6 +10,36 @@ pub fn css(c: &Color) -> String {
     }
 }
 
+// `#rrggbb`, or `#rrggbbaa` when it isn't opaque, which is what theme files use
+pub fn hex(c: &Color) -> String {
+    if c.a == 255 {
+        format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b)
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", c.r, c.g, c.b, c.a)
+    }
+}
+
+// `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`
+pub fn parse(text: &str) -> Option<Color> {
+    let text = text.trim();
+
+    if !text.starts_with('#') || !text[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let digits = &text[1..];
+    let channel = |i: usize, len: usize| u8::from_str_radix(&digits[i * len..(i + 1) * len], 16).ok();
+    let short = |i: usize| channel(i, 1).map(|c| c * 17);
+
+    match digits.len() {
+        3 => Some(Color { r: short(0)?, g: short(1)?, b: short(2)?, a: 255 }),
+        4 => Some(Color { r: short(0)?, g: short(1)?, b: short(2)?, a: short(3)? }),
+        6 => Some(Color { r: channel(0, 2)?, g: channel(1, 2)?, b: channel(2, 2)?, a: 255 }),
+        8 => Some(Color { r: channel(0, 2)?, g: channel(1, 2)?, b: channel(2, 2)?, a: channel(3, 2)? }),
+        _ => None,
+    }
+}
+
 // RGB <--> HSL conversions lifted from:
 // https://stackoverflow.com/questions/2353211/hsl-to-rgb-color-conversion
 //
@@ -111,6 +141,31 @@ pub fn alpha(color: &Color, alpha_perc: f32) -> Color {
     }
 }
 
+// hue, saturation and lightness, each 0-1
+pub fn to_hsl(color: &Color) -> (f32, f32, f32) {
+    rgb_to_hsl(color.r, color.g, color.b)
+}
+
+pub fn from_hsl(hue: f32, sat: f32, lum: f32, alpha: u8) -> Color {
+    let clamp = |n: f32| n.clamp(0.0, 1.0);
+    let rgb = hsl_to_rgb(hue - hue.floor(), clamp(sat), clamp(lum));
+
+    Color { r: rgb.0, g: rgb.1, b: rgb.2, a: alpha }
+}
+
+// `amount` of `base` mixed with the rest from `other`. The alpha channel is
+// only mixed if asked, otherwise it stays the base's.
+pub fn blend(base: &Color, other: &Color, amount: f32, with_alpha: bool) -> Color {
+    let mix = |a: u8, b: u8| (a as f32 * amount + b as f32 * (1.0 - amount)).round() as u8;
+
+    Color {
+        r: mix(base.r, other.r),
+        g: mix(base.g, other.g),
+        b: mix(base.b, other.b),
+        a: if with_alpha { mix(base.a, other.a) } else { base.a },
+    }
+}
+
 // consider a color "light" if luminance > 40%
 pub fn is_light(color: &Color) -> bool {
     rgb_to_hsl(color.r, color.g, color.b).2 > 0.40
@@ -0,0 +1,308 @@
+// Minimal unified diff parser, enough to handle `diff -u` and `git diff`
+// output. Anything outside of a hunk that isn't a file header is ignored.
+//
+// Also has the line alignment used to compare two files side by side.
+
+use std::cmp;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Context,
+    Added,
+    Removed,
+}
+
+
+#[derive(Debug)]
+pub struct Line {
+    pub kind: Kind,
+    pub text: String,
+    pub old: Option<usize>,
+    pub new: Option<usize>,
+}
+
+
+#[derive(Debug)]
+pub struct Hunk {
+    pub header: String,
+    pub lines: Vec<Line>,
+}
+
+
+#[derive(Debug, Default)]
+pub struct FileDiff {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FileDiff {
+    // the name to show in the header and to pick a syntax from. Deleted files
+    // only have an old path, so fall back to that.
+    pub fn name(&self) -> &str {
+        self.new_path.as_ref()
+            .or(self.old_path.as_ref())
+            .map_or("unknown", |s| s.as_str())
+    }
+
+    // (additions, deletions)
+    pub fn stats(&self) -> (usize, usize) {
+        let mut added = 0;
+        let mut removed = 0;
+
+        for line in self.hunks.iter().flat_map(|h| h.lines.iter()) {
+            match line.kind {
+                Kind::Added => added += 1,
+                Kind::Removed => removed += 1,
+                Kind::Context => (),
+            }
+        }
+
+        (added, removed)
+    }
+}
+
+
+// `--- a/src/main.rs` -> `src/main.rs`, `/dev/null` -> None
+fn parse_path(text: &str) -> Option<String> {
+    // timestamps from `diff -u` come after a tab
+    let path = text.split('\t').next().unwrap_or("").trim();
+
+    if path == "/dev/null" || path.is_empty() {
+        return None;
+    }
+
+    if path.starts_with("a/") || path.starts_with("b/") {
+        Some(String::from(&path[2..]))
+    } else {
+        Some(String::from(path))
+    }
+}
+
+
+// `-12,3` -> (12, 3), `+5` -> (5, 1)
+fn parse_range(text: &str) -> (usize, usize) {
+    let mut parts = text[1..].split(',').map(|n| n.parse().unwrap_or(0));
+    let start = parts.next().unwrap_or(0);
+    let count = parts.next().unwrap_or(1);
+
+    (start, count)
+}
+
+
+// `@@ -1,5 +1,6 @@ fn main() {` -> ((1, 5), (1, 6))
+fn parse_hunk_header(line: &str) -> Option<((usize, usize), (usize, usize))> {
+    let mut parts = line.split_whitespace().skip(1);
+    let old = parts.next()?;
+    let new = parts.next()?;
+
+    if !old.starts_with('-') || !new.starts_with('+') {
+        return None;
+    }
+
+    Some((parse_range(old), parse_range(new)))
+}
+
+
+pub fn parse(input: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = Vec::new();
+
+    // lines left in the current hunk for each side. Counting these (instead
+    // of looking at prefixes) is the only way to tell a removed line that
+    // starts with "--" apart from the next file header
+    let mut old_left: usize = 0;
+    let mut new_left: usize = 0;
+    let mut old_num = 0;
+    let mut new_num = 0;
+
+    for line in input.lines() {
+        if old_left > 0 || new_left > 0 {
+            let kind = match line.chars().next() {
+                Some('+') => Kind::Added,
+                Some('-') => Kind::Removed,
+                Some('\\') => continue, // "\ No newline at end of file"
+                _ => Kind::Context,
+            };
+
+            // the prefix is one byte when there is one; some tools drop the
+            // space in front of context lines
+            let text = if kind != Kind::Context || line.starts_with(' ') { &line[1..] } else { line };
+            let mut entry = Line { kind, text: String::from(text), old: None, new: None };
+
+            if kind != Kind::Added {
+                entry.old = Some(old_num);
+                old_num += 1;
+                old_left = old_left.saturating_sub(1);
+            }
+
+            if kind != Kind::Removed {
+                entry.new = Some(new_num);
+                new_num += 1;
+                new_left = new_left.saturating_sub(1);
+            }
+
+            if let Some(hunk) = files.last_mut().and_then(|f| f.hunks.last_mut()) {
+                hunk.lines.push(entry);
+            }
+
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            let mut file = FileDiff::default();
+            let mut paths = rest.split_whitespace();
+
+            file.old_path = paths.next().and_then(parse_path);
+            file.new_path = paths.next().and_then(parse_path);
+            files.push(file);
+        } else if let Some(rest) = line.strip_prefix("--- ") {
+            // plain `diff -u` output has no "diff --git" line to start a file
+            let started = files.last().is_some_and(|f| f.hunks.is_empty());
+
+            if !started {
+                files.push(FileDiff::default());
+            }
+
+            files.last_mut().unwrap().old_path = parse_path(rest);
+        } else if let Some(rest) = line.strip_prefix("+++ ") {
+            if let Some(file) = files.last_mut() {
+                file.new_path = parse_path(rest);
+            }
+        } else if line.starts_with("@@") {
+            if let Some(((old_start, old_count), (new_start, new_count))) = parse_hunk_header(line) {
+                if files.is_empty() {
+                    files.push(FileDiff::default());
+                }
+
+                old_num = old_start;
+                new_num = new_start;
+                old_left = old_count;
+                new_left = new_count;
+
+                files.last_mut().unwrap().hunks.push(Hunk {
+                    header: String::from(line),
+                    lines: Vec::new(),
+                });
+            }
+        }
+    }
+
+    files.retain(|f| !f.hunks.is_empty());
+    files
+}
+
+
+
+// Lines the two sides have in common, as a table of LCS lengths for every
+// (old, new) suffix. Plain O(n*m) dynamic programming is fine for the size of
+// file that's worth putting side by side in a document, `align` won't use it
+// past MAX_TABLE cells.
+fn lcs_table(old: &[&str], new: &[&str]) -> Vec<Vec<usize>> {
+    let mut table = vec![vec![0; new.len() + 1]; old.len() + 1];
+
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            table[i][j] = if old[i] == new[j] {
+                table[i + 1][j + 1] + 1
+            } else {
+                cmp::max(table[i + 1][j], table[i][j + 1])
+            };
+        }
+    }
+
+    table
+}
+
+
+// ~32MB of table
+const MAX_TABLE: usize = 4_000_000;
+
+
+// Lines up two files for a side-by-side view. Each row has the index of the
+// old line, the new line, or both. Runs of removed and added lines are paired
+// up with each other so a changed line sits next to its replacement.
+pub fn align(old: &[&str], new: &[&str]) -> Vec<(Option<usize>, Option<usize>)> {
+    // skip the common prefix & suffix so the table only covers what changed
+    let prefix = old.iter().zip(new.iter()).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+                    .zip(new[prefix..].iter().rev())
+                    .take_while(|&(a, b)| a == b)
+                    .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // too much changed to line up: the lines between are removed, then added
+    let table = if old_mid.len().saturating_mul(new_mid.len()) > MAX_TABLE {
+        eprintln!("note: too many changed lines to line up, showing them unaligned");
+        None
+    } else {
+        Some(lcs_table(old_mid, new_mid))
+    };
+
+    let mut rows = (0..prefix).map(|i| (Some(i), Some(i))).collect::<Vec<_>>();
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    let mut i = 0;
+    let mut j = 0;
+
+    while i < old_mid.len() || j < new_mid.len() {
+        if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+            pair_up(&mut rows, &mut removed, &mut added);
+            rows.push((Some(prefix + i), Some(prefix + j)));
+            i += 1;
+            j += 1;
+        } else if j >= new_mid.len() || (i < old_mid.len() && table.as_ref().is_none_or(|t| t[i + 1][j] >= t[i][j + 1])) {
+            removed.push(prefix + i);
+            i += 1;
+        } else {
+            added.push(prefix + j);
+            j += 1;
+        }
+    }
+
+    pair_up(&mut rows, &mut removed, &mut added);
+
+    for k in 0..suffix {
+        rows.push((Some(old.len() - suffix + k), Some(new.len() - suffix + k)));
+    }
+
+    rows
+}
+
+
+fn pair_up(rows: &mut Vec<(Option<usize>, Option<usize>)>,
+           removed: &mut Vec<usize>,
+           added: &mut Vec<usize>) {
+
+    for k in 0..cmp::max(removed.len(), added.len()) {
+        rows.push((removed.get(k).cloned(), added.get(k).cloned()));
+    }
+
+    removed.clear();
+    added.clear();
+}
+
+
+// The part of each line that differs, as byte ranges: everything between the
+// common prefix and the common suffix.
+pub fn changed_ranges(old: &str, new: &str) -> ((usize, usize), (usize, usize)) {
+    let prefix = old.char_indices()
+                    .zip(new.chars())
+                    .take_while(|&((_, a), b)| a == b)
+                    .last()
+                    .map_or(0, |((i, a), _)| i + a.len_utf8());
+
+    let max_suffix = cmp::min(old.len(), new.len()) - prefix;
+    let suffix = old[prefix..].chars().rev()
+                    .zip(new[prefix..].chars().rev())
+                    .take_while(|&(a, b)| a == b)
+                    .map(|(a, _)| a.len_utf8())
+                    .scan(0, |total, len| { *total += len; Some(*total) })
+                    .take_while(|&total| total <= max_suffix)
+                    .last()
+                    .unwrap_or(0);
+
+    ((prefix, old.len() - suffix), (prefix, new.len() - suffix))
+}
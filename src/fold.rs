@@ -0,0 +1,183 @@
+// Works out which regions of a file can be collapsed. Regions are inclusive
+// ranges of 0-based line indexes, and are always properly nested so they can
+// be rendered as nested <details> blocks.
+
+use syntect::parsing::{ParseState, Scope, ScopeStack, BasicScopeStackOp, SyntaxDefinition};
+
+use paint;
+
+
+// anything shorter than this isn't worth folding automatically
+const MIN_LINES: usize = 4;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fold {
+    Off,
+    // 1-based, inclusive line numbers
+    Ranges(Vec<(usize, usize)>),
+    Indent,
+    Scopes,
+}
+
+
+// `indent`, `scopes`, or `20-80,90-120`
+pub fn parse(setting: &str) -> Option<Fold> {
+    match setting {
+        "indent" => return Some(Fold::Indent),
+        "scopes" => return Some(Fold::Scopes),
+        _ => (),
+    }
+
+    let mut ranges = Vec::new();
+
+    for section in setting.split(',') {
+        let mut ns = section.trim().splitn(2, '-').map(|n| n.parse::<usize>());
+        let start = ns.next()?.ok()?;
+        let end = ns.next()?.ok()?;
+
+        ranges.push((start, end));
+    }
+
+    Some(Fold::Ranges(ranges))
+}
+
+
+fn indent(line: &str, tab_width: usize) -> Option<usize> {
+    if line.trim().is_empty() {
+        return None;
+    }
+
+    Some(paint::leading_width(line, tab_width))
+}
+
+
+// Everything indented further than the line before it, not counting trailing
+// blank lines
+fn by_indent(lines: &[&str], tab_width: usize) -> Vec<(usize, usize)> {
+    let indents = lines.iter().map(|l| indent(l, tab_width)).collect::<Vec<_>>();
+    let mut regions = Vec::new();
+
+    for (i, level) in indents.iter().enumerate() {
+        let level = match *level {
+            Some(level) => level,
+            None => continue,
+        };
+
+        let mut last = i;
+
+        for (j, other) in indents.iter().enumerate().skip(i + 1) {
+            match *other {
+                Some(other) if other > level => last = j,
+                Some(_) => break,
+                None => (),
+            }
+        }
+
+        if last > i {
+            regions.push((i + 1, last));
+        }
+    }
+
+    regions
+}
+
+
+// Everything between the first and last line of a `meta.block` or
+// `meta.function` scope, so the opening and closing lines stay visible
+fn by_scopes(lines: &[&str], syntax: &SyntaxDefinition) -> Vec<(usize, usize)> {
+    let foldable = [
+        Scope::new("meta.block").unwrap(),
+        Scope::new("meta.function").unwrap(),
+    ];
+
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut starts: Vec<Option<usize>> = Vec::new();
+    let mut regions = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let ops = state.parse_line(line);
+
+        for (_, op) in &ops {
+            stack.apply_with_hook(op, |basic, _| match basic {
+                BasicScopeStackOp::Push(scope) => {
+                    let fold = foldable.iter().any(|f| f.is_prefix_of(scope));
+                    starts.push(if fold { Some(i) } else { None });
+                }
+                BasicScopeStackOp::Pop => {
+                    if let Some(Some(start)) = starts.pop() {
+                        if i > start + 1 {
+                            regions.push((start + 1, i - 1));
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    regions
+}
+
+
+// Drops anything too small, duplicated, or crossing a region we already have.
+// Sorted outermost first.
+fn nest(mut regions: Vec<(usize, usize)>, min_lines: usize) -> Vec<(usize, usize)> {
+    regions.retain(|&(start, end)| end >= start && end - start + 1 >= min_lines);
+    regions.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+    regions.dedup();
+
+    let mut kept: Vec<(usize, usize)> = Vec::new();
+
+    for region in regions {
+        let crosses = kept.iter().any(|k| {
+            (region.0 > k.0 && region.0 <= k.1 && region.1 > k.1) ||
+            (region.0 < k.0 && region.1 >= k.0 && region.1 < k.1)
+        });
+
+        if !crosses {
+            kept.push(region);
+        }
+    }
+
+    kept
+}
+
+
+// Regions to fold, clipped to whatever part of the file is being shown
+pub fn regions(text: &str,
+               syntax: &SyntaxDefinition,
+               fold: &Fold,
+               first_line: usize,
+               selection: Option<(usize, usize)>,
+               tab_width: usize) -> Vec<(usize, usize)> {
+
+    let lines = text.lines().collect::<Vec<_>>();
+
+    let (found, min_lines) = match *fold {
+        Fold::Off => return Vec::new(),
+        Fold::Indent => (by_indent(&lines, tab_width), MIN_LINES),
+        Fold::Scopes => (by_scopes(&lines, syntax), MIN_LINES),
+        Fold::Ranges(ref ranges) => {
+            let found = ranges.iter()
+                .filter(|&&(start, end)| start >= first_line && end >= start)
+                .map(|&(start, end)| (start - first_line, end - first_line))
+                .collect();
+
+            (found, 1)
+        }
+    };
+
+    let (low, high) = match selection {
+        Some((start, finish)) => (start.saturating_sub(first_line), finish.saturating_sub(first_line)),
+        None => (0, lines.len()),
+    };
+
+    let last = lines.len().saturating_sub(1);
+
+    let clipped = found.into_iter()
+        .map(|(start, end)| (start.max(low), end.min(high).min(last)))
+        .collect();
+
+    nest(clipped, min_lines)
+}
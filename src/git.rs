@@ -0,0 +1,183 @@
+// Talks to the local `git` executable. Everything here works off of the
+// repository on disk, nothing touches the network.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use diff::{self, Kind};
+
+
+#[derive(Debug, Clone)]
+pub struct BlameLine {
+    pub sha: String,
+    pub author: String,
+    pub time: u64,
+}
+
+impl BlameLine {
+    pub fn short_sha(&self) -> &str {
+        &self.sha[..7]
+    }
+
+    // lines that haven't been committed yet are all zeroes
+    pub fn is_committed(&self) -> bool {
+        self.sha.chars().any(|c| c != '0')
+    }
+}
+
+
+fn run(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format!("couldn't run git: {}", e))?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+
+// the directory holding `path`, so git works no matter where paint was run from
+fn dir_of(path: &str) -> &Path {
+    match Path::new(path).parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name()
+        .map_or(String::from(path), |n| n.to_string_lossy().into_owned())
+}
+
+
+// One entry per line of the file (at `rev`, if given), in order
+pub fn blame(path: &str, rev: Option<&str>) -> Result<Vec<BlameLine>, String> {
+    let name = file_name(path);
+    let mut args = vec!["blame", "--porcelain"];
+
+    if let Some(rev) = rev {
+        args.push(rev);
+    }
+
+    args.push("--");
+    args.push(&name);
+
+    let output = run(dir_of(path), &args)?;
+
+    // commit info is only given the first time a commit shows up
+    let mut commits: HashMap<String, (String, u64)> = HashMap::new();
+    let mut lines = Vec::new();
+    let mut sha = String::new();
+
+    for line in output.lines() {
+        if line.starts_with('\t') {
+            let &(ref author, time) = commits.get(&sha).unwrap();
+
+            lines.push(BlameLine {
+                sha: sha.clone(),
+                author: author.clone(),
+                time,
+            });
+
+            continue;
+        }
+
+        let mut parts = line.splitn(2, ' ');
+        let key = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("");
+
+        if key.len() == 40 && key.chars().all(|c| c.is_ascii_hexdigit()) {
+            sha = String::from(key);
+            commits.entry(sha.clone()).or_insert((String::new(), 0));
+        } else if key == "author" {
+            commits.get_mut(&sha).unwrap().0 = String::from(value);
+        } else if key == "author-time" {
+            commits.get_mut(&sha).unwrap().1 = value.parse().unwrap_or(0);
+        }
+    }
+
+    Ok(lines)
+}
+
+
+// Contents of `path` as of `rev`. Paths are relative to the current directory
+// like any other FILE, not to the root of the repository.
+pub fn show(rev: &str, path: &str) -> Result<String, String> {
+    let spec = if Path::new(path).is_relative() && !path.starts_with("./") {
+        format!("{}:./{}", rev, path)
+    } else {
+        format!("{}:{}", rev, path)
+    };
+
+    run(Path::new("."), &["show", &spec])
+}
+
+
+// Line numbers in `path` (at `rev`, or the working copy) that were added or
+// changed since `base`
+pub fn changed_lines(base: &str, rev: Option<&str>, path: &str) -> Result<Vec<usize>, String> {
+    let mut args = vec!["diff", "--no-color", "--no-ext-diff", "-U0", base];
+
+    if let Some(rev) = rev {
+        args.push(rev);
+    }
+
+    args.push("--");
+    args.push(path);
+
+    let output = run(Path::new("."), &args)?;
+
+    let lines = diff::parse(&output).iter()
+        .flat_map(|file| file.hunks.iter())
+        .flat_map(|hunk| hunk.lines.iter())
+        .filter(|line| line.kind == Kind::Added)
+        .filter_map(|line| line.new)
+        .collect();
+
+    Ok(lines)
+}
+
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+
+// "3 months ago", plus a bucket from 0 (newest) to 4 (oldest) for coloring
+pub fn age(time: u64, now: u64) -> (String, usize) {
+    let secs = now.saturating_sub(time);
+    let days = secs / 86_400;
+
+    let (amount, unit, bucket) = if days < 1 {
+        (secs / 3600, "hour", 0)
+    } else if days < 7 {
+        (days, "day", 0)
+    } else if days < 30 {
+        (days / 7, "week", 1)
+    } else if days < 182 {
+        (days / 30, "month", 2)
+    } else if days < 365 {
+        (days / 30, "month", 3)
+    } else {
+        (days / 365, "year", 4)
+    };
+
+    let text = match amount {
+        0 => String::from("just now"),
+        1 => format!("1 {} ago", unit),
+        n => format!("{} {}s ago", n, unit),
+    };
+
+    (text, bucket)
+}
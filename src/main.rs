@@ -1,8 +1,10 @@
 use std::io::{self, Read, Write};
-use std::collections::HashSet;
-use std::path::Path;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::fs::{self, File};
+use std::env;
 use std::process;
+use std::iter;
 use std::sync::mpsc::channel;
 use std::time::Duration;
 
@@ -11,17 +13,28 @@ extern crate clap;
 extern crate syntect;
 extern crate notify;
 extern crate regex;
+extern crate serde_json;
 
-use clap::{App, ArgMatches, SubCommand};
-use syntect::highlighting::{Theme, ThemeSet};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use syntect::highlighting::{Color, Theme, ThemeSet};
 use syntect::parsing::{SyntaxSet, SyntaxDefinition};
 use syntect::dumps;
 use regex::{Regex, Captures};
+use serde_json::{Map, Value};
 use notify::{RecommendedWatcher, Watcher, RecursiveMode};
 
+mod annotate;
 mod color;
+mod diff;
+mod fold;
+mod git;
 mod paint;
+mod scopes;
+mod sublime;
+mod theme;
+mod vscode;
 use paint::Config;
+use scopes::ClassStyle;
 
 
 fn main() {
@@ -30,12 +43,20 @@ fn main() {
         -o, --out=[file]         'Save result to file instead of stdout'
         --filetype=[type]        'Specify the filetype when using stdin'
         --embed                  'Emit a js embed script instead of html'
-        -t, --theme=[name/path]  'Theme name or .tmTheme path, (defaults to "github")'
+        -t, --theme=[name/path]  'Theme name or .tmTheme/.json/.sublime-color-scheme path, (defaults to "github")'
+        --theme-light=[name/path] 'Light theme for light/dark output, (defaults to --theme)'
+        --theme-dark=[name/path] 'Dark theme, used when the reader prefers a dark color scheme'
         --syntax=[file]          'Use given .sublime-syntax for syntax parsing'
+        --theme-dir=[dir]        'Also load .tmTheme files from this directory'
+        --syntax-dir=[dir]       'Also load .sublime-syntax files from this directory'
         --html-only              'Output html only'
         --css-only               'Output css only'
         --css-inline             'Put styles inline instead of using classes'
         --css-prefix=[prefix]    'CSS style prefix, defaults to ".paint"'
+        --css-vars               'Put theme colors in CSS custom properties'
+        --css-used-only          'Only output CSS rules the html actually uses'
+        --class-style=[style]    'Name token classes after theme colors or scopes: theme|scopes'
+        --hash-classes           'Name color classes after a hash of their css, not a number'
         -n, --line-numbers       'Include line numbers'
         -b, --border             'Wrap output in a border'
         -h, --header             'Adds header'
@@ -43,12 +64,35 @@ fn main() {
         -g, --gist-like          'Adds line numbers, border, and header'
         --title=[string]         'Title to use for the header or footer'
         --highlight=[lines]      'Highlight lines: X[-Y][,...]'
+        --mark=[ranges]          'Mark columns within lines: LINE:COL[-COL][,...]'
         --selection=[lines]      'Only include range of lines: N-M'
+        --first-line=[n]         'Number lines starting from n, (defaults to 1)'
+        --number-every=[n]       'Only show every nth line number'
+        --number-pad=[style]     'Pad line numbers to the same width: zero|space'
+        --relative=[line]        'Number lines relative to a focus line'
+        --callouts               'Turn trailing <N> markers in the code into numbered badges'
+        --anchors                'Give each line an id and make line numbers links'
+        --id=[name]              'Id for this block, used in line anchors'
+        --copy-button            'Add a button that copies the plain source'
+        --raw-link               'Add a link to download the plain source'
+        --fold=[regions]         'Collapse regions: indent|scopes|N-M[,...]'
+        --wrap                   'Wrap long lines instead of scrolling'
+        --font-family=[fonts]    'Font stack for the code'
+        --font-size=[size]       'Font size for the code, defaults to 12px'
+        --line-height=[height]   'Line height for the code, defaults to 20px'
+        --header-font=[fonts]    'Font stack for the header and footer'
+        --tab-width=[widths]     'Tab width, with optional per-language widths: N[,LANG=N...]'
+        --expand-tabs            'Replace tabs with spaces in the output'
     "#;
 
-    let replace_usage = format!("{}\n{}",
+    let compare_usage = usage.replace(
+        "[FILE]                   'File to highlight'",
+        "<OLD> 'Original version of the file'\n<NEW> 'Changed version of the file'");
+
+    let replace_usage = format!("{}\n{}\n{}",
                                 usage,
-                                "-w, --watch 'Watch input file for changes'");
+                                "-w, --watch 'Watch input file for changes'",
+                                "--css-out=[file] 'Write the stylesheet to a file and link to it'");
 
     let args = App::new("paint")
         .about("A sublime text style syntax highlighter that outputs HTML\n
@@ -56,6 +100,13 @@ EXAMPLE:
     paint ./file.xx --theme=\"oceanic next\" > index.html")
         .version(crate_version!())
         .args_from_usage(usage)
+        // only for a single file, so not part of the usage the subcommands share
+        .arg(Arg::from_usage("--blame 'Show git blame info next to each line'"))
+        .arg(Arg::from_usage("--highlight-changed=[rev] 'Highlight lines changed since rev, (defaults to HEAD)'")
+            .min_values(0)
+            .require_equals(true))
+        .arg(Arg::from_usage("--annotate=[note]... 'Attach a numbered note to a line: LINE=TEXT or <N>=TEXT'")
+            .number_of_values(1))
 
         .subcommand(SubCommand::with_name("replace")
             .about(r#"Replaces html <pre> blocks in <FILE> with a highlighted version.
@@ -63,12 +114,47 @@ You need to specify language type w/: <pre data-paint="xx">
 Enable watch mode with --watch"#)
             .args_from_usage(&replace_usage))
 
+        .subcommand(SubCommand::with_name("diff")
+            .about(r#"Highlights a unified diff (or `git diff` output) from <FILE> or stdin.
+Code is highlighted with the syntax matching each file's name"#)
+            .args_from_usage(usage))
+
+        .subcommand(SubCommand::with_name("compare")
+            .about("Shows two versions of a file side by side, with changes highlighted")
+            .args_from_usage(&compare_usage))
+
         .subcommand(SubCommand::with_name("css")
             .about("Generate css for a theme")
-            .args_from_usage("[theme] 'Get css for this theme'"))
-
-        .subcommand(SubCommand::with_name("list-themes"))
-        .subcommand(SubCommand::with_name("list-syntaxes"))
+            .args_from_usage("[theme] 'Get css for this theme'
+                              --theme-dark=[name/path] 'Dark theme, used when the reader prefers a dark color scheme'
+                              --class-style=[style] 'Name token classes after theme colors or scopes: theme|scopes'
+                              --hash-classes 'Name color classes after a hash of their css, not a number'
+                              --theme-dir=[dir] 'Also load .tmTheme files from this directory'"))
+
+        .subcommand(SubCommand::with_name("convert-theme")
+            .about("Convert a VS Code .json or .sublime-color-scheme theme to a .tmTheme")
+            .args_from_usage("<FILE> 'Theme to convert'
+                              -o, --out=[file] 'Save result to file instead of stdout'"))
+
+        .subcommand(SubCommand::with_name("gallery")
+            .about("Shows a sample file in every theme, on one page")
+            .args_from_usage("--sample=<file> 'File to show in each theme'
+                              --filetype=[type] 'Specify the filetype, (defaults to the extension)'
+                              --selection=[lines] 'Only include range of lines: N-M'
+                              -n, --line-numbers 'Include line numbers'
+                              -o, --out=[file] 'Save result to file instead of stdout'
+                              --theme-dir=[dir] 'Also load .tmTheme files from this directory'
+                              --syntax-dir=[dir] 'Also load .sublime-syntax files from this directory'"))
+
+        .subcommand(SubCommand::with_name("anchors-js")
+            .about("Print the script that handles #L10-L20 style links, for use with --anchors"))
+
+        .subcommand(SubCommand::with_name("list-themes")
+            .args_from_usage("--theme-dir=[dir] 'Also load .tmTheme files from this directory'
+                              --json 'Print details about each theme as JSON'"))
+        .subcommand(SubCommand::with_name("list-syntaxes")
+            .args_from_usage("--syntax-dir=[dir] 'Also load .sublime-syntax files from this directory'
+                              --json 'Print details about each syntax as JSON'"))
 
         // development commands, easier to add here
         .subcommand(SubCommand::with_name("dump-themes")
@@ -78,12 +164,17 @@ Enable watch mode with --watch"#)
         .get_matches();
 
     match args.subcommand() {
-        ("list-themes", _)         => list_themes(),
-        ("list-syntaxes", _)       => list_syntaxes(),
+        ("anchors-js", _)          => println!("{}", paint::anchors_script()),
+        ("list-themes", Some(a))   => list_themes(a),
+        ("list-syntaxes", Some(a)) => list_syntaxes(a),
         ("dump-themes", Some(a))   => dump_themes(a.value_of("folder").unwrap()),
         ("dump-syntaxes", Some(a)) => dump_syntaxes(a.value_of("folder").unwrap()),
         ("css", Some(a))           => make_css(a),
+        ("convert-theme", Some(a)) => convert_theme(a),
+        ("gallery", Some(a))       => gallery(a),
         ("replace", Some(a))       => replace(a),
+        ("diff", Some(a))          => diff(a),
+        ("compare", Some(a))       => compare(a),
         _                          => highlight(&args),
     };
 }
@@ -95,36 +186,182 @@ fn get_included_themes() -> ThemeSet {
 }
 
 
-fn get_syntaxes() -> SyntaxSet {
+// `~/.config/paint/<kind>`, then the directory given with --<flag>. Later
+// directories win when they have something with the same name.
+fn user_dirs(args: &ArgMatches, kind: &str, flag: &str) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    let config = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").or(env::var_os("USERPROFILE")).map(|home| Path::new(&home).join(".config")));
+
+    if let Some(config) = config {
+        let dir = config.join("paint").join(kind);
+        if dir.is_dir() { dirs.push(dir); }
+    }
+
+    if let Some(dir) = args.value_of(flag) {
+        if !Path::new(dir).is_dir() {
+            eprintln!("--{} '{}' isn't a directory", flag, dir);
+            process::exit(1);
+        }
+
+        dirs.push(PathBuf::from(dir));
+    }
+
+    dirs
+}
+
+
+// Bundled themes plus any theme files in the user theme directories, named
+// like the bundled ones after the lowercased file name
+fn get_theme_set(args: &ArgMatches) -> ThemeSet {
+    get_themes_and_files(args).0
+}
+
+
+// Also gives the file each user theme came from, by name
+fn get_themes_and_files(args: &ArgMatches) -> (ThemeSet, HashMap<String, PathBuf>) {
+    let mut set = get_included_themes();
+    let mut files = HashMap::new();
+
+    for dir in user_dirs(args, "themes", "theme-dir") {
+        for path in theme::discover(&dir) {
+            let name = path.file_stem().unwrap().to_string_lossy().to_lowercase();
+
+            match theme::load(&path) {
+                Ok(theme) => {
+                    set.themes.insert(name.clone(), theme);
+                    files.insert(name, path);
+                }
+                Err(e) => eprintln!("skipping theme '{}': {}", path.display(), e),
+            }
+        }
+    }
+
+    (set, files)
+}
+
+
+// Syntaxes from the user directories, then the bundled ones they don't
+// replace. Lookups take the first match, so user syntaxes come first and a
+// bundled syntax with the same name is left out.
+fn get_syntaxes(args: &ArgMatches) -> SyntaxSet {
+    get_syntaxes_and_sources(args).0
+}
+
+
+// Also says where each syntax came from, in the same order as the set:
+// a user file, "syntect" for syntect's defaults, or "bundled" for our packdump
+fn get_syntaxes_and_sources(args: &ArgMatches) -> (SyntaxSet, Vec<String>) {
+    let mut set = SyntaxSet::new();
+    let mut sources = Vec::new();
+
+    for dir in user_dirs(args, "syntaxes", "syntax-dir").iter().rev() {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("skipping syntax directory '{}': {}", dir.display(), e);
+                continue;
+            }
+        };
+
+        let mut paths = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|e| e == "sublime-syntax"))
+            .collect::<Vec<_>>();
+
+        paths.sort();
+
+        for path in paths {
+            let syntax = fs::read_to_string(&path).map_err(|e| format!("{}", e))
+                .and_then(|data| SyntaxDefinition::load_from_str(&data, false, None).map_err(|e| format!("{:?}", e)));
+
+            match syntax {
+                Ok(syntax) => {
+                    if !set.syntaxes().iter().any(|s| s.name == syntax.name) {
+                        set.add_syntax(syntax);
+                        sources.push(path.display().to_string());
+                    }
+                }
+                Err(e) => eprintln!("skipping syntax '{}': {}", path.display(), e),
+            }
+        }
+    }
+
     let bytes = include_bytes!("../assets/syntaxes.packdump");
     let extra: SyntaxSet = dumps::from_binary(bytes);
+    let defaults = SyntaxSet::load_defaults_nonewlines();
 
-    let mut defaults = SyntaxSet::load_defaults_nonewlines();
+    let bundled = defaults.syntaxes().iter().map(|s| (s, "syntect"))
+        .chain(extra.syntaxes().iter().map(|s| (s, "bundled")));
 
-    for syntax in extra.syntaxes() {
-        defaults.add_syntax(syntax.to_owned());
+    for (syntax, source) in bundled {
+        if !set.syntaxes().iter().any(|s| s.name == syntax.name) {
+            set.add_syntax(syntax.to_owned());
+            sources.push(String::from(source));
+        }
     }
 
-    defaults.link_syntaxes();
-    defaults
+    set.link_syntaxes();
+    (set, sources)
 }
 
 
-fn list_themes() {
-    println!("Included themes:");
+fn list_themes(args: &ArgMatches) {
+    let (set, files) = get_themes_and_files(args);
 
-    for name in get_included_themes().themes.keys() {
-        println!("- {}", name);
+    if !args.is_present("json") {
+        println!("Included themes:");
+
+        for name in set.themes.keys() {
+            println!("- {}", name);
+        }
+
+        return;
     }
+
+    let themes = set.themes.iter().map(|(name, theme)| {
+        let bg = theme.settings.background.unwrap_or(Color::WHITE);
+        let fg = theme.settings.foreground.unwrap_or(Color::BLACK);
+
+        let mut entry = Map::new();
+        entry.insert("name".into(), Value::from(name.as_str()));
+        entry.insert("author".into(), theme.author.as_ref().map_or(Value::Null, |a| Value::from(a.as_str())));
+        entry.insert("kind".into(), Value::from(if color::is_light(&bg) { "light" } else { "dark" }));
+        entry.insert("background".into(), Value::from(color::hex(&bg)));
+        entry.insert("foreground".into(), Value::from(color::hex(&fg)));
+        entry.insert("source".into(), Value::from(files.get(name).map_or(String::from("bundled"), |p| p.display().to_string())));
+        Value::Object(entry)
+    }).collect();
+
+    println!("{}", serde_json::to_string_pretty(&Value::Array(themes)).unwrap());
 }
 
 
-fn list_syntaxes() {
-    println!("Included syntaxes:");
+fn list_syntaxes(args: &ArgMatches) {
+    let (set, sources) = get_syntaxes_and_sources(args);
+
+    if !args.is_present("json") {
+        println!("Included syntaxes:");
+
+        for syntax in set.syntaxes() {
+            println!("- {}", syntax.name);
+        }
 
-    for syntax in get_syntaxes().syntaxes() {
-        println!("- {}", syntax.name);
+        return;
     }
+
+    let syntaxes = set.syntaxes().iter().zip(sources).map(|(syntax, source)| {
+        let mut entry = Map::new();
+        entry.insert("name".into(), Value::from(syntax.name.as_str()));
+        entry.insert("file_extensions".into(), Value::from(syntax.file_extensions.clone()));
+        entry.insert("first_line_match".into(), syntax.first_line_match.as_ref().map_or(Value::Null, |m| Value::from(m.as_str())));
+        entry.insert("scope".into(), Value::from(syntax.scope.build_string()));
+        entry.insert("source".into(), Value::from(source));
+        Value::Object(entry)
+    }).collect();
+
+    println!("{}", serde_json::to_string_pretty(&Value::Array(syntaxes)).unwrap());
 }
 
 
@@ -150,7 +387,7 @@ fn parse_selection(lines: Option<&str>) -> Option<(usize, usize)> {
     match lines {
         None => None,
         Some(s) => {
-            let ns = s.split('-').map(|n| n.parse().unwrap()).collect::<Vec<_>>();
+            let ns = s.split('-').map(|n| n.parse::<usize>().unwrap()).collect::<Vec<_>>();
             let start = ns.get(0).expect("Problem parsing selection number");
             let end = ns.get(1).unwrap_or(&start);
 
@@ -183,19 +420,190 @@ fn parse_highlighted(lines: Option<&str>) -> HashSet<usize> {
 }
 
 
+// `12:5-18,14:3` -> {12: [(5, 18)], 14: [(3, 3)]}
+fn parse_marks(marks: Option<&str>) -> HashMap<usize, Vec<(usize, usize)>> {
+    let mut parsed = HashMap::new();
+
+    let fail = |mark: &str| -> ! {
+        eprintln!("Problem parsing mark '{}', expected LINE:COL[-COL]", mark);
+        process::exit(1);
+    };
+
+    if let Some(sections) = marks {
+        for mark in sections.split(',') {
+            let mut parts = mark.trim().splitn(2, ':');
+            let line = parts.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| fail(mark));
+            let cols = parts.next().unwrap_or_else(|| fail(mark));
+
+            let ns = cols.split('-')
+                         .map(|n| n.parse::<usize>().unwrap_or_else(|_| fail(mark)))
+                         .collect::<Vec<_>>();
+
+            let start = ns[0];
+            let end = *ns.get(1).unwrap_or(&start);
+
+            parsed.entry(line).or_insert_with(Vec::new).push((start, end));
+        }
+    }
+
+    parsed
+}
+
+
+fn parse_annotations(notes: Option<Vec<&str>>) -> Vec<(annotate::Note, String)> {
+    notes.unwrap_or_default().into_iter().map(|note| {
+        annotate::parse(note).unwrap_or_else(|| {
+            eprintln!("Problem parsing annotation '{}', expected LINE=TEXT or <N>=TEXT", note);
+            process::exit(1);
+        })
+    }).collect()
+}
+
+
+// `main.rs` -> `main-rs`, something safe to use in an element id
+fn slug(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+
+fn parse_fold(setting: Option<&str>) -> fold::Fold {
+    match setting {
+        None => fold::Fold::Off,
+        Some(s) => fold::parse(s).unwrap_or_else(|| {
+            eprintln!("--fold expects 'indent', 'scopes' or line ranges N-M[,...], got '{}'", s);
+            process::exit(1);
+        }),
+    }
+}
+
+
+fn parse_class_style(setting: Option<&str>) -> ClassStyle {
+    match setting {
+        Some(s) => scopes::parse(s).unwrap_or_else(|| {
+            eprintln!("invalid class style '{}', expected theme|scopes", s);
+            process::exit(1);
+        }),
+        None => ClassStyle::Theme,
+    }
+}
+
+
+// scope classes only mean something next to a stylesheet
+fn check_class_style(config: &Config) {
+    if config.class_style == ClassStyle::Scopes && config.inline {
+        eprintln!("--class-style=scopes can't be used with --css-inline");
+        process::exit(1);
+    }
+}
+
+
+fn parse_number(setting: Option<&str>, name: &str) -> Option<usize> {
+    setting.map(|n| n.parse().unwrap_or_else(|_| {
+        eprintln!("--{} expects a number, got '{}'", name, n);
+        process::exit(1);
+    }))
+}
+
+
+fn parse_number_pad(setting: Option<&str>) -> Option<char> {
+    match setting {
+        None => None,
+        Some("zero") => Some('0'),
+        Some("space") => Some(' '),
+        Some(other) => {
+            eprintln!("--number-pad expects 'zero' or 'space', got '{}'", other);
+            process::exit(1);
+        }
+    }
+}
+
+
+// bare numbers are taken as pixels, anything else is passed through to the CSS
+fn parse_length(setting: &str) -> String {
+    match setting.trim().parse::<f64>() {
+        Ok(_) => format!("{}px", setting.trim()),
+        Err(_) => String::from(setting.trim()),
+    }
+}
+
+
+// `4` or `4,go=8,make=8`, the first width is the default for everything else
+fn parse_tab_width(setting: Option<&str>) -> (usize, HashMap<String, usize>) {
+    let mut width = 4;
+    let mut widths = HashMap::new();
+
+    let fail = |s: &str| -> ! {
+        eprintln!("invalid tab width '{}', expected N[,LANG=N...]", s);
+        process::exit(1);
+    };
+
+    for section in setting.unwrap_or("").split(',').filter(|s| !s.trim().is_empty()) {
+        let mut parts = section.splitn(2, '=').map(|p| p.trim());
+        let first = parts.next().unwrap();
+
+        match parts.next() {
+            Some(n) => {
+                let n = n.parse().unwrap_or_else(|_| fail(section));
+                widths.insert(first.to_lowercase(), n);
+            }
+            None => width = first.parse().unwrap_or_else(|_| fail(section)),
+        }
+    }
+
+    (width, widths)
+}
+
+
+// swap in the language's own tab width, if one was given. Languages can be
+// named by filetype token, syntax name, or any of the syntax's extensions.
+fn resolve_tab_width(config: &mut Config, filetype: &str, syntax: &SyntaxDefinition) {
+    let names = iter::once(filetype.to_lowercase())
+        .chain(iter::once(syntax.name.to_lowercase()))
+        .chain(syntax.file_extensions.iter().map(|e| e.to_lowercase()));
+
+    for name in names {
+        if let Some(&width) = config.tab_widths.get(&name) {
+            config.tab_width = width;
+            return;
+        }
+    }
+}
+
+
 fn get_input_from(args: &ArgMatches) -> String {
     if args.value_of("FILE").or(args.value_of("filetype")).is_none() {
         eprintln!("missing FILE to highlight (or use --filetype with stdin)");
         process::exit(1);
     }
 
+    read_input(args)
+}
+
+
+// read FILE, or stdin if there isn't one
+fn read_input(args: &ArgMatches) -> String {
     let mut input = String::new();
 
     match args.value_of("FILE") {
-        Some(file) => File::open(file)
-                           .expect("Can't open file!")
-                           .read_to_string(&mut input)
-                           .unwrap(),
+        Some(file) => match split_revision(file) {
+            Some((rev, path)) => {
+                input = git::show(rev, path).unwrap_or_else(|e| {
+                    eprintln!("Problem reading '{}' from git:\n{}", file, e);
+                    process::exit(1);
+                });
+
+                input.len()
+            }
+
+            None => File::open(file)
+                        .expect("Can't open file!")
+                        .read_to_string(&mut input)
+                        .unwrap(),
+        },
 
         None => io::stdin()
                    .read_to_string(&mut input)
@@ -206,20 +614,35 @@ fn get_input_from(args: &ArgMatches) -> String {
 }
 
 
-fn get_theme(setting: Option<&str>) -> Theme {
-    let set = get_included_themes();
+// FILE can also be `rev:path`, like `HEAD~3:src/main.rs`. Anything that exists
+// on disk is taken as a plain path, so a drive letter like `C:\` is left alone.
+fn split_revision(file: &str) -> Option<(&str, &str)> {
+    if Path::new(file).exists() {
+        return None;
+    }
+
+    let colon = file.find(':')?;
+    let (rev, path) = (&file[..colon], &file[colon + 1..]);
 
+    if rev.is_empty() || path.is_empty() {
+        None
+    } else {
+        Some((rev, path))
+    }
+}
+
+
+fn get_theme(set: &ThemeSet, setting: Option<&str>) -> Theme {
     // get theme from a name or path
     if let Some(name) = setting {
-        let theme = if set.themes.contains_key(name) {
-            Ok(set.themes[name].to_owned())
-        } else {
-            ThemeSet::get_theme(name)
+        let theme = match set.themes.get(name).or(set.themes.get(&name.to_lowercase())) {
+            Some(theme) => Ok(theme.to_owned()),
+            None => theme::load(Path::new(name)),
         };
 
         if theme.is_err() {
             eprintln!(
-                "'{}' is not included or there was a problem with the theme file:\n{:?}",
+                "'{}' is not included or there was a problem with the theme file:\n{}",
                 name,
                 theme.unwrap_err());
 
@@ -233,10 +656,34 @@ fn get_theme(setting: Option<&str>) -> Theme {
 }
 
 
+// --theme-light wins over --theme when making light/dark output
+fn get_themes(args: &ArgMatches, set: &ThemeSet) -> (Theme, Option<Theme>) {
+    let light = get_theme(set, args.value_of("theme-light").or(args.value_of("theme")));
+    let dark = args.value_of("theme-dark").map(|name| get_theme(set, Some(name)));
+
+    (light, dark)
+}
+
+
+// side by side and diff views only come in one theme
+fn single_theme(args: &ArgMatches, set: &ThemeSet, command: &str) -> Theme {
+    if args.is_present("theme-dark") {
+        eprintln!("--theme-dark isn't supported by `paint {}`", command);
+        process::exit(1);
+    }
+
+    get_theme(set, args.value_of("theme"))
+}
+
+
 fn make_config(args: &ArgMatches) -> Config {
 
     let filename = if let Some(file) = args.value_of("FILE") {
-        Path::new(file).file_name().unwrap().to_string_lossy().into_owned()
+        match split_revision(file) {
+            Some((rev, path)) => format!("{} @ {}",
+                Path::new(path).file_name().unwrap().to_string_lossy(), rev),
+            None => Path::new(file).file_name().unwrap().to_string_lossy().into_owned(),
+        }
     } else {
         String::from("stdin")
     };
@@ -248,17 +695,45 @@ fn make_config(args: &ArgMatches) -> Config {
                      .map(|s| String::from(s))
                      .unwrap_or(String::from("paint"));
 
+    let (tab_width, tab_widths) = parse_tab_width(args.value_of("tab-width"));
+
     Config {
-        filename:    filename,
+        filename:    filename.clone(),
         title:       title,
-        css_prefix:  prefix,
+        css_prefix:  prefix.clone(),
+        page_prefix: prefix,
         inline:      args.is_present("css-inline"),
         numbers:     args.is_present("line-numbers") || args.is_present("gist-like"),
         border:      args.is_present("border") || args.is_present("gist-like"),
         header:      args.is_present("header") || args.is_present("gist-like"),
         footer:      args.is_present("footer"),
         highlighted: parse_highlighted(args.value_of("highlight")),
+        marks:       parse_marks(args.value_of("mark")),
         selection:   parse_selection(args.value_of("selection")),
+        first_line:  parse_number(args.value_of("first-line"), "first-line").unwrap_or(1),
+        every:       parse_number(args.value_of("number-every"), "number-every").unwrap_or(1),
+        number_pad:  parse_number_pad(args.value_of("number-pad")),
+        relative:    parse_number(args.value_of("relative"), "relative"),
+        blame:       None,
+        annotations: parse_annotations(args.values_of("annotate").map(|v| v.collect())),
+        callouts:    args.is_present("callouts"),
+        anchors:     args.is_present("anchors"),
+        id:          args.value_of("id").map_or(slug(&filename), String::from),
+        copy:        args.is_present("copy-button"),
+        raw:         args.is_present("raw-link"),
+        fold:        parse_fold(args.value_of("fold")),
+        wrap:        args.is_present("wrap"),
+        font_family: args.value_of("font-family").map(String::from),
+        font_size:   args.value_of("font-size").map(parse_length),
+        line_height: args.value_of("line-height").map(parse_length),
+        header_font: args.value_of("header-font").map(String::from),
+        tab_width,
+        tab_widths,
+        expand_tabs: args.is_present("expand-tabs"),
+        css_vars:    args.is_present("css-vars"),
+        css_used_only: args.is_present("css-used-only"),
+        class_style: parse_class_style(args.value_of("class-style")),
+        hash_classes: args.is_present("hash-classes"),
     }
 }
 
@@ -273,7 +748,30 @@ fn modify_config(mut config: Config, pre: &str) -> Config {
     let numbers = Regex::new(r#"^<pre.*?data-line-numbers.*?>"#).unwrap();
     let title   = Regex::new(r#"^<pre.*?data-title="(.+?)".*?>"#).unwrap();
     let high    = Regex::new(r#"^<pre.*?data-highlight="(.+?)".*?>"#).unwrap();
+    let mark    = Regex::new(r#"^<pre.*?data-mark="(.+?)".*?>"#).unwrap();
+    let notes   = Regex::new(r#"^<pre.*?data-annotate="(.+?)".*?>"#).unwrap();
+    let callout = Regex::new(r#"^<pre.*?data-callouts.*?>"#).unwrap();
+    let anchors = Regex::new(r#"^<pre.*?data-anchors.*?>"#).unwrap();
+    let id      = Regex::new(r#"^<pre.*?data-id="(.+?)".*?>"#).unwrap();
+    let copy    = Regex::new(r#"^<pre.*?data-copy-button.*?>"#).unwrap();
+    let raw     = Regex::new(r#"^<pre.*?data-raw-link.*?>"#).unwrap();
+    let folds   = Regex::new(r#"^<pre.*?data-fold="(.+?)".*?>"#).unwrap();
+    let wrap    = Regex::new(r#"^<pre.*?data-wrap.*?>"#).unwrap();
     let prefix  = Regex::new(r#"^<pre.*?data-css-prefix="(.+?)".*?>"#).unwrap();
+    let first   = Regex::new(r#"^<pre.*?data-first-line="(.+?)".*?>"#).unwrap();
+    let every   = Regex::new(r#"^<pre.*?data-number-every="(.+?)".*?>"#).unwrap();
+    let pad     = Regex::new(r#"^<pre.*?data-number-pad="(.+?)".*?>"#).unwrap();
+    let rel     = Regex::new(r#"^<pre.*?data-relative="(.+?)".*?>"#).unwrap();
+    let family  = Regex::new(r#"^<pre.*?data-font-family="(.+?)".*?>"#).unwrap();
+    let size    = Regex::new(r#"^<pre.*?data-font-size="(.+?)".*?>"#).unwrap();
+    let height  = Regex::new(r#"^<pre.*?data-line-height="(.+?)".*?>"#).unwrap();
+    let hfont   = Regex::new(r#"^<pre.*?data-header-font="(.+?)".*?>"#).unwrap();
+    let tabs    = Regex::new(r#"^<pre.*?data-tab-width="(.+?)".*?>"#).unwrap();
+    let expand  = Regex::new(r#"^<pre.*?data-expand-tabs.*?>"#).unwrap();
+    let vars    = Regex::new(r#"^<pre.*?data-css-vars.*?>"#).unwrap();
+    let used    = Regex::new(r#"^<pre.*?data-css-used-only.*?>"#).unwrap();
+    let classes = Regex::new(r#"^<pre.*?data-class-style="(.+?)".*?>"#).unwrap();
+    let hashed  = Regex::new(r#"^<pre.*?data-hash-classes.*?>"#).unwrap();
 
     if gist.captures(pre).is_some() {
         config.header = true;
@@ -295,8 +793,79 @@ fn modify_config(mut config: Config, pre: &str) -> Config {
         config.highlighted = parse_highlighted(Some(&c[1]));
     }
 
+    if let Some(c) = mark.captures(pre) {
+        config.marks = parse_marks(Some(&c[1]));
+    }
+
+    // multiple notes are separated by "|", ex: data-annotate="3=first|9=second"
+    if let Some(c) = notes.captures(pre) {
+        config.annotations = parse_annotations(Some(c[1].split('|').collect()));
+    }
+
+    if callout.captures(pre).is_some() { config.callouts = true; }
+    if anchors.captures(pre).is_some() { config.anchors = true; }
+    if copy.captures(pre).is_some() { config.copy = true; }
+    if raw.captures(pre).is_some() { config.raw = true; }
+    if wrap.captures(pre).is_some() { config.wrap = true; }
+
+    if let Some(c) = folds.captures(pre) {
+        config.fold = parse_fold(Some(&c[1]));
+    }
+
+    if let Some(c) = id.captures(pre) {
+        config.id = slug(&c[1]);
+    }
+
     if let Some(c) = prefix.captures(pre) {
         config.css_prefix = String::from(&c[1]);
+        config.page_prefix = String::from(&c[1]);
+    }
+
+    if let Some(c) = first.captures(pre) {
+        config.first_line = parse_number(Some(&c[1]), "first-line").unwrap();
+    }
+
+    if let Some(c) = every.captures(pre) {
+        config.every = parse_number(Some(&c[1]), "number-every").unwrap();
+    }
+
+    if let Some(c) = pad.captures(pre) {
+        config.number_pad = parse_number_pad(Some(&c[1]));
+    }
+
+    if let Some(c) = rel.captures(pre) {
+        config.relative = parse_number(Some(&c[1]), "relative");
+    }
+
+    if let Some(c) = family.captures(pre) {
+        config.font_family = Some(String::from(&c[1]));
+    }
+
+    if let Some(c) = size.captures(pre) {
+        config.font_size = Some(parse_length(&c[1]));
+    }
+
+    if let Some(c) = height.captures(pre) {
+        config.line_height = Some(parse_length(&c[1]));
+    }
+
+    if let Some(c) = hfont.captures(pre) {
+        config.header_font = Some(String::from(&c[1]));
+    }
+
+    if let Some(c) = tabs.captures(pre) {
+        let (width, widths) = parse_tab_width(Some(&c[1]));
+        config.tab_width = width;
+        config.tab_widths = widths;
+    }
+
+    if expand.captures(pre).is_some() { config.expand_tabs = true; }
+    if vars.captures(pre).is_some() { config.css_vars = true; }
+    if used.captures(pre).is_some() { config.css_used_only = true; }
+    if hashed.captures(pre).is_some() { config.hash_classes = true; }
+
+    if let Some(c) = classes.captures(pre) {
+        config.class_style = parse_class_style(Some(&c[1]));
     }
 
     config
@@ -332,28 +901,108 @@ fn find_syntax<'a>(mut token: &str, set: &'a SyntaxSet) -> &'a SyntaxDefinition
 
 fn highlight_string(input: &str,
                     filetype: &str,
+                    set: &SyntaxSet,
                     syntax_path: Option<&str>,
                     theme: &Theme,
-                    config: &Config) -> (String, String) {
+                    dark: Option<&Theme>,
+                    mut config: Config) -> (String, String) {
+
+    // inline styles can't change with the color scheme
+    if dark.is_some() && config.inline {
+        eprintln!("--theme-dark needs classes, it can't be used with --css-inline");
+        process::exit(1);
+    }
+
+    check_class_style(&config);
 
     // ownership issue, need syntax sets higher in scope so they don't get dropped
-    let set = get_syntaxes();
     let mut temp_set = SyntaxSet::new();
+    let syntax = pick_syntax(filetype, set, syntax_path, &mut temp_set);
 
-    let syntax = match syntax_path {
-        Some(path) => make_syntax(path, &mut temp_set),
-        None       => find_syntax(filetype, &set),
-    };
+    resolve_tab_width(&mut config, filetype, syntax);
+    paint::highlight(input, syntax, theme, dark, &config)
+}
 
-    paint::highlight(&input, &syntax, theme, &config)
+
+// a syntax file from --syntax, or the one for the filetype
+fn pick_syntax<'a>(filetype: &str,
+                   set: &'a SyntaxSet,
+                   syntax_path: Option<&'a str>,
+                   temp_set: &'a mut SyntaxSet) -> &'a SyntaxDefinition {
+    match syntax_path {
+        Some(path) => make_syntax(path, temp_set),
+        None       => find_syntax(filetype, set),
+    }
 }
 
 
 fn make_css(args: &ArgMatches) {
-    let theme = get_theme(args.value_of("theme"));
+    let set = get_theme_set(args);
+    let theme = get_theme(&set, args.value_of("theme"));
+    let dark = args.value_of("theme-dark").map(|name| get_theme(&set, Some(name)));
     let config = make_config(args);
 
-    println!("{}", paint::css(&theme, &config));
+    println!("{}", paint::css(&theme, dark.as_ref(), &config));
+}
+
+
+fn convert_theme(args: &ArgMatches) {
+    let path = args.value_of("FILE").unwrap();
+
+    let theme = theme::load(Path::new(path)).unwrap_or_else(|e| {
+        eprintln!("Problem loading '{}':\n{}", path, e);
+        process::exit(1);
+    });
+
+    let out = theme::to_tmtheme(&theme);
+
+    match args.value_of("out") {
+        Some(file) => write_to_file(&out, file).unwrap(),
+        None => print!("{}", out),
+    }
+}
+
+
+fn gallery(args: &ArgMatches) {
+    let path = args.value_of("sample").unwrap();
+
+    let text = theme::read(Path::new(path)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    let filetype = args.value_of("filetype")
+                       .unwrap_or_else(|| path.rsplit('.').next().unwrap());
+
+    let set = get_syntaxes(args);
+    let syntax = find_syntax(filetype, &set);
+
+    // every theme gets its own prefix so they can share a page. Names that
+    // slug the same, like `Solarized (dark)` & `solarized-dark`, get a counter
+    let mut taken = HashSet::new();
+
+    let cards = get_theme_set(args).themes.into_iter().map(|(name, theme)| {
+        let mut config = make_config(args);
+        config.filename = Path::new(path).file_name().unwrap().to_string_lossy().into_owned();
+
+        let base = format!("{}-{}", config.css_prefix, slug(&name));
+        let mut prefixes = iter::once(base.clone()).chain((2..).map(|n| format!("{}-{}", base, n)));
+        config.css_prefix = prefixes.find(|p| taken.insert(p.clone())).unwrap();
+        config.page_prefix = config.css_prefix.clone();
+        config.border = true;
+        config.header = true;
+        resolve_tab_width(&mut config, filetype, syntax);
+
+        let (html, css) = paint::highlight(&text, syntax, &theme, None, &config);
+        (name, theme, html, css)
+    }).collect::<Vec<_>>();
+
+    let page = paint::gallery(&cards);
+
+    match args.value_of("out") {
+        Some(file) => write_to_file(&page, file).unwrap(),
+        None => println!("{}", page),
+    }
 }
 
 
@@ -363,39 +1012,208 @@ fn replace_pre_blocks(input: &str, args: &ArgMatches) -> String {
         r#"<pre.*?data-paint="([\w\d]+)".*?>([\s\S]*?)</pre>"#
     ).unwrap();
 
-    let theme_re = Regex::new(r#"^<pre.*?data-theme="(.+?)".*?>"#).unwrap();
+    let theme_re = Regex::new(r#"^<pre.*?data-theme(?:-light)?="(.+?)".*?>"#).unwrap();
+    let dark_re = Regex::new(r#"^<pre.*?data-theme-dark="(.+?)".*?>"#).unwrap();
     let html_only = Regex::new(r#"^<pre.*?data-html-only.*?>"#).unwrap();
     let css_inline = Regex::new(r#"^<pre.*?data-css-inline.*?>"#).unwrap();
+    let prefix_re = Regex::new(r#"^<pre.*?data-css-prefix=".+?".*?>"#).unwrap();
+
+    // the light & dark theme names a block asks for
+    let block_themes = |outer: &str| {
+        let light = theme_re.captures(outer).map(|c| String::from(&c[1]))
+            .or(args.value_of("theme-light").or(args.value_of("theme")).map(String::from))
+            .unwrap_or(String::from("github"));
+
+        let dark = dark_re.captures(outer).map(|c| String::from(&c[1]))
+            .or(args.value_of("theme-dark").map(String::from));
+
+        (light, dark)
+    };
+
+    // Blocks in different themes would fight over `.paint .pt3`, so when a
+    // page mixes themes each one gets its own prefix, like `.paint-github`.
+    // A prefix set by hand is left alone.
+    let mut themes = Vec::new();
+    for cap in pre.captures_iter(input) {
+        let names = block_themes(&cap[0]);
+        if !themes.contains(&names) {
+            themes.push(names);
+        }
+    }
+
+    let namespace = themes.len() > 1 && !args.is_present("css-prefix");
+
+    let set = get_syntaxes(args);
+    let theme_set = get_theme_set(args);
+    let default_themes = get_themes(args, &theme_set);
+    let mut block = 0;
+
+    // Blocks with the same prefix share one stylesheet, the rules of all of
+    // them. A block whose settings would redefine one of those rules (say a
+    // different tab width for `.paint table`) gets a numbered prefix of its
+    // own instead: `.paint-2`. The stylesheet doesn't depend on the code, so
+    // that's settled before highlighting.
+    let mut sheets: Vec<(String, String)> = Vec::new();
 
     let file_contents = pre.replace_all(&input, |cap: &Captures| {
         let outer = &cap[0];
         let inner = &cap[2].trim();
         let filetype = &cap[1];
 
-        // override settings per code block
-        let config = modify_config(make_config(&args), &outer);
+        // every block needs its own id so line anchors don't collide
+        block += 1;
 
         // theme could be different per block too
         let theme = match theme_re.captures(outer) {
-            Some(c) => get_theme(Some(&c[1])),
-            None    => get_theme(args.value_of("theme")),
+            Some(c) => get_theme(&theme_set, Some(&c[1])),
+            None    => default_themes.0.clone(),
         };
 
+        let dark = match dark_re.captures(outer) {
+            Some(c) => Some(get_theme(&theme_set, Some(&c[1]))),
+            None    => default_themes.1.clone(),
+        };
+
+        let mut config = make_config(args);
+        config.id = format!("{}-{}", config.id, block);
+
+        if namespace && prefix_re.captures(outer).is_none() {
+            config.css_prefix = theme_prefix(&config.css_prefix, &block_themes(outer));
+        }
+
+        // override settings per code block
+        let mut config = modify_config(config, outer);
+
         let syntax_path = args.value_of("syntax");
-        let (html, css) = highlight_string(inner, filetype, syntax_path, &theme, &config);
 
         let no_css = args.is_present("html-only") ||
                      html_only.captures(outer).is_some() ||
                      css_inline.captures(outer).is_some();
 
         if no_css {
-            html
-        } else {
-            format!("<style scoped>{}</style>\n{}", &css, &html)
+            return highlight_string(inner, filetype, &set, syntax_path, &theme, dark.as_ref(), config).0;
         }
+
+        // every rule the block could have, --css-used-only picks from these
+        let mut temp_set = SyntaxSet::new();
+        let syntax = pick_syntax(filetype, &set, syntax_path, &mut temp_set);
+        resolve_tab_width(&mut config, filetype, syntax);
+
+        let used_only = config.css_used_only;
+        config.css_used_only = false;
+
+        let base = config.css_prefix.clone();
+        let prefixes = iter::once(base.clone()).chain((2..).map(|n| format!("{}-{}", base, n)));
+
+        for prefix in prefixes {
+            config.css_prefix = prefix;
+            let fits = match sheets.iter().find(|(p, _)| *p == config.css_prefix) {
+                Some((_, sheet)) => !paint::css_conflicts(sheet, &paint::stylesheet(&theme, dark.as_ref(), &config)),
+                None => true,
+            };
+
+            if fits { break; }
+        }
+
+        config.css_used_only = used_only;
+
+        let prefix = config.css_prefix.clone();
+        let (html, css) = highlight_string(inner, filetype, &set, syntax_path, &theme, dark.as_ref(), config);
+
+        match sheets.iter_mut().find(|&&mut (ref p, _)| *p == prefix) {
+            Some(&mut (_, ref mut sheet)) => *sheet = paint::merge_css(sheet, &css),
+            None => sheets.push((prefix, css)),
+        }
+
+        html
     });
 
-    file_contents.to_string()
+    let file_contents = file_contents.to_string();
+
+    if sheets.is_empty() {
+        return file_contents;
+    }
+
+    let css = sheets.into_iter().map(|(_, sheet)| sheet).collect::<Vec<_>>().join("\n");
+
+    let tag = match args.value_of("css-out") {
+        Some(path) => {
+            let (file, href) = css_file_name(path, &css, args.value_of("out"));
+            write_to_file(&css, &file).unwrap();
+            format!("<link rel='stylesheet' href='{}'>", href)
+        }
+        None => format!("<style>\n{}\n</style>", css),
+    };
+
+    // into <head> if there is one, otherwise at the top of the fragment
+    match file_contents.find("</head>") {
+        Some(i) => format!("{}{}\n{}", &file_contents[..i], tag, &file_contents[i..]),
+        None => format!("{}\n{}", tag, file_contents),
+    }
+}
+
+
+// `paint` + `oceanic next` -> `paint-oceanic-next`. Themes given as a path are
+// named after the file.
+fn theme_prefix(prefix: &str, themes: &(String, Option<String>)) -> String {
+    let name = |theme: &str| {
+        let stem = Path::new(theme).file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+        slug(&stem)
+    };
+
+    match themes.1 {
+        Some(ref dark) => format!("{}-{}-{}", prefix, name(&themes.0), name(dark)),
+        None => format!("{}-{}", prefix, name(&themes.0)),
+    }
+}
+
+
+// `dist/paint.css` -> `dist/paint.1a2b3c4d.css`, so a changed stylesheet gets
+// a new name and can be cached forever. Returns the path to write to and the
+// href to use from the output file.
+fn css_file_name(path: &str, css: &str, out: Option<&str>) -> (String, String) {
+    let hash = format!("{:016x}", paint::stable_hash(css));
+
+    let path = Path::new(path);
+    let stem = path.file_stem().map_or(String::from("paint"), |s| s.to_string_lossy().into_owned());
+    let ext = path.extension().map_or(String::from("css"), |s| s.to_string_lossy().into_owned());
+    let file = path.with_file_name(format!("{}.{}.{}", stem, &hash[..8], ext));
+
+    // links are relative to the html, not to wherever paint was run from
+    let out_dir = out.and_then(|o| Path::new(o).parent()).unwrap_or(Path::new(""));
+    let href = relative_path(out_dir, &file);
+
+    (file.to_string_lossy().into_owned(), href.to_string_lossy().replace('\\', "/"))
+}
+
+
+// `out` + `assets/paint.css` -> `../assets/paint.css`. Only looks at the
+// paths themselves, symlinks aren't followed.
+fn relative_path(from_dir: &Path, to: &Path) -> PathBuf {
+    let absolute = |path: &Path| {
+        let path = env::current_dir().map(|dir| dir.join(path)).unwrap_or(path.to_path_buf());
+        let mut parts = Vec::new();
+
+        for part in path.components() {
+            match part {
+                Component::CurDir => {}
+                Component::ParentDir => { parts.pop(); }
+                _ => parts.push(part.as_os_str().to_os_string()),
+            }
+        }
+
+        parts
+    };
+
+    let from = absolute(from_dir);
+    let to = absolute(to);
+    let common = from.iter().zip(to.iter()).take_while(|&(a, b)| a == b).count();
+
+    let mut path = PathBuf::new();
+    for _ in common..from.len() { path.push(".."); }
+    for part in &to[common..] { path.push(part); }
+
+    path
 }
 
 
@@ -475,6 +1293,44 @@ fn replace(args: &ArgMatches) {
 }
 
 
+fn get_blame(args: &ArgMatches) -> Vec<git::BlameLine> {
+    let path = args.value_of("FILE").unwrap_or_else(|| {
+        eprintln!("--blame needs a FILE inside a git repository");
+        process::exit(1);
+    });
+
+    let blame = match split_revision(path) {
+        Some((rev, file)) => git::blame(file, Some(rev)),
+        None => git::blame(path, None),
+    };
+
+    blame.unwrap_or_else(|e| {
+        eprintln!("Problem getting blame info for '{}':\n{}", path, e);
+        process::exit(1);
+    })
+}
+
+
+fn get_changed_lines(args: &ArgMatches) -> Vec<usize> {
+    let path = args.value_of("FILE").unwrap_or_else(|| {
+        eprintln!("--highlight-changed needs a FILE inside a git repository");
+        process::exit(1);
+    });
+
+    let base = args.value_of("highlight-changed").unwrap_or("HEAD");
+
+    let changed = match split_revision(path) {
+        Some((rev, file)) => git::changed_lines(base, Some(rev), file),
+        None => git::changed_lines(base, None, path),
+    };
+
+    changed.unwrap_or_else(|e| {
+        eprintln!("Problem finding changes to '{}' since {}:\n{}", path, base, e);
+        process::exit(1);
+    })
+}
+
+
 fn highlight(args: &ArgMatches) {
     let input = get_input_from(&args);
 
@@ -482,19 +1338,134 @@ fn highlight(args: &ArgMatches) {
                    args.value_of("FILE").unwrap().split(".").last().unwrap());
 
     let syntax = args.value_of("syntax");
-    let theme  = get_theme(args.value_of("theme"));
-    let config = make_config(&args);
+    let (theme, dark) = get_themes(args, &get_theme_set(args));
+    let mut config = make_config(args);
 
-    let (html, css) = highlight_string(&input, &filetype, syntax, &theme, &config);
+    if args.is_present("blame") {
+        config.blame = Some(get_blame(args));
+    }
 
+    if args.is_present("highlight-changed") {
+        let offset = config.first_line;
+
+        for line in get_changed_lines(args) {
+            config.highlighted.insert(line + offset - 1);
+        }
+    }
+
+    let set = get_syntaxes(args);
+    let (html, css) = highlight_string(&input, filetype, &set, syntax, &theme, dark.as_ref(), config);
+
+    emit(args, &html, &css, &theme, dark.as_ref());
+}
+
+
+fn diff(args: &ArgMatches) {
+    let input = read_input(args);
+    let files = diff::parse(&input);
+
+    if files.is_empty() {
+        eprintln!("no hunks found, is the input a unified diff?");
+        process::exit(1);
+    }
+
+    let set = get_syntaxes(args);
+    let mut temp_set = SyntaxSet::new();
+
+    let custom = args.value_of("syntax").map(|path| make_syntax(path, &mut temp_set));
+
+    // diffs always get the full gist-like treatment
+    let mut config = make_config(args);
+    let default_tab_width = config.tab_width;
+
+    let with_syntaxes = files.iter().map(|file| {
+        let filetype = args.value_of("filetype")
+                           .unwrap_or_else(|| file.name().rsplit('.').next().unwrap());
+        let syntax = custom.unwrap_or_else(|| find_syntax(filetype, &set));
+
+        config.tab_width = default_tab_width;
+        resolve_tab_width(&mut config, filetype, syntax);
+
+        (file, syntax, config.tab_width)
+    }).collect::<Vec<_>>();
+
+    config.tab_width = default_tab_width;
+    config.numbers = true;
+    config.border = true;
+    config.header = true;
+
+    let theme = single_theme(args, &get_theme_set(args), "diff");
+    check_class_style(&config);
+    let (html, css) = paint::diff(&with_syntaxes, &theme, &config);
+
+    emit(args, &html, &css, &theme, None);
+}
+
+
+fn compare(args: &ArgMatches) {
+    let old_path = args.value_of("OLD").unwrap();
+    let new_path = args.value_of("NEW").unwrap();
+
+    let read = |path: &str| {
+        let mut text = String::new();
+
+        File::open(path)
+            .expect("Can't open file!")
+            .read_to_string(&mut text)
+            .unwrap();
+
+        text
+    };
+
+    let old = read(old_path);
+    let new = read(new_path);
+
+    let filetype = args.value_of("filetype")
+                       .unwrap_or_else(|| new_path.rsplit('.').next().unwrap());
+
+    let set = get_syntaxes(args);
+    let mut temp_set = SyntaxSet::new();
+
+    let syntax = match args.value_of("syntax") {
+        Some(path) => make_syntax(path, &mut temp_set),
+        None       => find_syntax(filetype, &set),
+    };
+
+    // comparisons always get the full gist-like treatment
+    let mut config = make_config(args);
+    resolve_tab_width(&mut config, filetype, syntax);
+    config.numbers = true;
+    config.border = true;
+    config.header = true;
+
+    let theme = single_theme(args, &get_theme_set(args), "compare");
+    check_class_style(&config);
+    let name = |path: &str| Path::new(path).file_name().unwrap().to_string_lossy().into_owned();
+
+    let (html, css) = paint::compare(
+        (&name(old_path), &old),
+        (&name(new_path), &new),
+        syntax,
+        &theme,
+        &config);
+
+    emit(args, &html, &css, &theme, None);
+}
+
+
+// write highlighted output in whichever form was asked for
+fn emit(args: &ArgMatches, html: &str, css: &str, theme: &Theme, dark: Option<&Theme>) {
     let output = if args.is_present("css-only") {
-        css
+        String::from(css)
     } else if args.is_present("html-only") {
-        html
+        String::from(html)
     } else if args.is_present("embed") {
-        paint::embed_script(&html, &css)
+        paint::embed_script(html, css)
+    } else if args.is_present("anchors") {
+        let html = format!("{}\n<script>{}</script>", html, paint::anchors_script());
+        paint::fullpage(&html, css, theme, dark)
     } else {
-        paint::fullpage(&html, &css, &theme)
+        paint::fullpage(html, css, theme, dark)
     };
 
     match args.value_of("out") {
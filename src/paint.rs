@@ -1,11 +1,20 @@
+use std::cmp;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::fmt::Write;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use syntect::easy::HighlightLines;
 use syntect::parsing::SyntaxDefinition;
 use syntect::highlighting::{Theme, Style, Color, FontStyle};
 
 use color;
+use diff::{self, FileDiff, Kind};
+use git::{self, BlameLine};
+use annotate::{self, Annotations, Note};
+use fold::{self, Fold};
+use scopes::{self, ClassStyle, ScopeLines};
+use theme;
 
 
 // holds command line option
@@ -14,20 +23,121 @@ pub struct Config {
     pub inline: bool,
     pub numbers: bool,
     pub highlighted: HashSet<usize>,
+    pub marks: HashMap<usize, Vec<(usize, usize)>>,
     pub selection: Option<(usize, usize)>,
+    pub first_line: usize,
+    pub every: usize,
+    pub number_pad: Option<char>,
+    pub relative: Option<usize>,
+    pub blame: Option<Vec<BlameLine>>,
+    pub annotations: Vec<(Note, String)>,
+    pub callouts: bool,
+    pub anchors: bool,
+    pub id: String,
+    pub copy: bool,
+    pub raw: bool,
+    pub fold: Fold,
+    pub wrap: bool,
+    pub font_family: Option<String>,
+    pub font_size: Option<String>,
+    pub line_height: Option<String>,
+    pub header_font: Option<String>,
+    pub tab_width: usize,
+    pub tab_widths: HashMap<String, usize>,
+    pub expand_tabs: bool,
+    pub css_vars: bool,
+    pub css_used_only: bool,
+    pub class_style: ClassStyle,
+    pub hash_classes: bool,
     pub header: bool,
     pub footer: bool,
     pub border: bool,
     pub title: Option<String>,
     pub filename: String,
     pub css_prefix: String,
+    // Prefix for names the whole page shares: the light/dark switch classes,
+    // custom properties, and line anchors. Only differs from `css_prefix` when
+    // a block's theme has been namespaced.
+    pub page_prefix: String,
+}
+
+
+const MONO_FONTS: &str = "SFMono-Regular, Consolas, \"Liberation Mono\", Menlo, monospace";
+const SANS_FONTS: &str = "-apple-system, BlinkMacSystemFont, \"Segoe UI\", Roboto, Helvetica, Arial, sans-serif";
+
+
+// How a piece of code is styled. Light/dark output also carries the piece's
+// style in the dark theme, so one class can hold both colors.
+//
+// With scope classes, `scope` is the piece's entry in `CssGen::scope_classes`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Look {
+    style: Style,
+    dark: Option<Style>,
+    scope: Option<usize>,
+}
+
+impl Look {
+    fn from(style: Style) -> Look {
+        Look { style, dark: None, scope: None }
+    }
+}
+
+
+// Where the pieces of each line come from: one theme, a light and a dark
+// theme, or the scopes themselves
+enum Painter<'a> {
+    Plain(HighlightLines<'a>),
+    Dual(HighlightLines<'a>, HighlightLines<'a>),
+    Scopes(ScopeLines<'a>),
+}
+
+impl<'a> Painter<'a> {
+    fn new(syntax: &SyntaxDefinition,
+           theme: &'a Theme,
+           dark: Option<&'a Theme>,
+           config: &Config) -> Painter<'a> {
+
+        // scope classes don't depend on the theme, so they cover dark output as is
+        match (config.class_style, dark) {
+            (ClassStyle::Scopes, _) => Painter::Scopes(ScopeLines::new(syntax, theme)),
+            (ClassStyle::Theme, Some(dark)) => {
+                Painter::Dual(HighlightLines::new(syntax, theme), HighlightLines::new(syntax, dark))
+            }
+            (ClassStyle::Theme, None) => Painter::Plain(HighlightLines::new(syntax, theme)),
+        }
+    }
+
+    fn paint<'b>(&mut self, line: &'b str, css_gen: &CssGen) -> Vec<(Look, &'b str)> {
+        match *self {
+            Painter::Plain(ref mut h) => looks(h.highlight(line)),
+            Painter::Dual(ref mut light, ref mut dark) => {
+                pair_looks(&light.highlight(line), &dark.highlight(line))
+            }
+            Painter::Scopes(ref mut h) => {
+                h.highlight(line).into_iter()
+                    .map(|(style, classes, text)| (css_gen.scoped(style, classes), text))
+                    .collect()
+            }
+        }
+    }
 }
 
 
 // wrapper to keep all CSS generation together
 struct CssGen<'a> {
     config: &'a Config,
-    style_table: HashMap<String, String>,
+    theme: &'a Theme,
+    // declaration -> class, starts out with the theme's scope colors and
+    // grows if highlighting turns up any others
+    style_table: RefCell<BTreeMap<String, String>>,
+    // class attributes for scope stacks, see `Look`
+    scope_classes: RefCell<Vec<String>>,
+    // class attributes handed out so far, shared with the dark half too
+    used: Rc<RefCell<HashSet<String>>>,
+    // generator for the dark half, if making light/dark output
+    dark: Option<Box<CssGen<'a>>>,
+    is_dark: bool,
     fg: Color,
     bg: Color,
     header: Color,
@@ -37,64 +147,62 @@ struct CssGen<'a> {
 }
 
 impl<'a> CssGen<'a> {
-    fn from(theme: &Theme, config: &'a Config) -> CssGen<'a> {
+    fn from(theme: &'a Theme, config: &'a Config) -> CssGen<'a> {
         let bg = theme.settings.background.unwrap_or(Color::WHITE);
         let fg = theme.settings.foreground.unwrap_or(Color::BLACK);
 
-        let style_table = CssGen::make_style_table(theme, &fg, &bg);
-
-        // specific to the github theme
-        if theme.name.as_ref().unwrap() == "GitHub Light" {
-            return CssGen {
-                config,
-                style_table,
-                fg,
-                bg,
-                header:       Color { r: 249, g: 249, b: 249, a: 255 }, // #f9f9f9
-                border:       Color { r: 221, g: 221, b: 221, a: 255 }, // #dddddd
-                highlight:    Color { r: 255, g: 251, b: 221, a: 255 }, // #fffbdd
-                line_numbers: Color { r: 190, g: 191, b: 191, a: 255 }, // #bebfbf
-            }
-        }
+        let style_table = RefCell::new(CssGen::make_style_table(theme, &fg, &bg, config.hash_classes));
 
-        // light themes
-        if color::is_light(&bg) {
-            return CssGen {
-                config,
-                style_table,
-                fg,
-                bg,
-                header:       color::darken(&bg, 0.85, 0.95),
-                border:       Color { r: 204, g: 204, b: 204, a: 255 }, // #cccccc
-                highlight:    theme.settings.selection.unwrap(),
-                line_numbers: Color { r: 153, g: 153, b: 153, a: 170 }, // #999999
-            }
-        }
+        let (header, border, highlight, line_numbers) = if theme.name.as_ref().is_some_and(|name| name == "GitHub Light") {
+            // specific to the github theme
+            (
+                Color { r: 249, g: 249, b: 249, a: 255 }, // #f9f9f9
+                Color { r: 221, g: 221, b: 221, a: 255 }, // #dddddd
+                Color { r: 255, g: 251, b: 221, a: 255 }, // #fffbdd
+                Color { r: 190, g: 191, b: 191, a: 255 }, // #bebfbf
+            )
+        } else if color::is_light(&bg) {
+            // light themes
+            (
+                color::darken(&bg, 0.85, 0.95),
+                Color { r: 204, g: 204, b: 204, a: 255 }, // #cccccc
+                theme.settings.selection.unwrap_or(color::darken(&bg, 1.0, 0.92)),
+                Color { r: 153, g: 153, b: 153, a: 170 }, // #999999
+            )
+        } else {
+            // dark themes
+            let header = color::lighten(&bg, 0.65, 1.65);
+            let border = color::lighten(&header, 0.75, 1.35);
+            let highlight = color::lighten(&bg, 1.0, 1.35);
+            let line_numbers = color::alpha(&fg, 0.25);
 
-        // dark themes
-        let header = color::lighten(&bg, 0.65, 1.65);
-        let border = color::lighten(&header, 0.75, 1.35);
-        let highlight = color::lighten(&bg, 1.0, 1.35);
-        let line_numbers = color::alpha(&fg, 0.25);
+            (header, border, highlight, line_numbers)
+        };
 
+        // unless the theme picked its own, see theme.rs
         CssGen {
             config,
+            theme,
             style_table,
+            scope_classes: RefCell::new(Vec::new()),
+            used: Rc::new(RefCell::new(HashSet::new())),
+            dark: None,
+            is_dark: false,
             fg,
             bg,
-            header,
-            border,
-            highlight,
-            line_numbers,
+            header:       theme::chrome(theme, theme::HEADER).unwrap_or(header),
+            border:       theme::chrome(theme, theme::BORDER).unwrap_or(border),
+            highlight:    theme::chrome(theme, theme::HIGHLIGHT).unwrap_or(highlight),
+            line_numbers: theme::chrome(theme, theme::LINE_NUMBERS).unwrap_or(line_numbers),
         }
     }
 
-    fn make_style_table(theme: &Theme, fg: &Color, bg: &Color,)
-        -> HashMap<String, String>
+    fn make_style_table(theme: &Theme, fg: &Color, bg: &Color, hashed: bool)
+        -> BTreeMap<String, String>
     {
         let mut css = Vec::new();
 
-        for scope in &theme.scopes {
+        for scope in theme.scopes.iter().filter(|item| !theme::is_chrome(item)) {
             if let Some(fore) = scope.style.foreground {
                 if fore != *fg {
                     css.push(format!("color: {};", color::css(&fore)));
@@ -111,11 +219,15 @@ impl<'a> CssGen<'a> {
         css.sort();
         css.dedup();
 
-        let mut styles = HashMap::new();
+        let mut styles = BTreeMap::new();
         let mut i = 1;
 
         for style in css {
-            let class = format!("pt{}", i);
+            let class = if hashed {
+                hashed_class(&style, |class| styles.values().any(|c| c == class))
+            } else {
+                format!("pt{}", i)
+            };
             styles.insert(style, class);
             i += 1;
         }
@@ -123,16 +235,93 @@ impl<'a> CssGen<'a> {
         styles
     }
 
+    // The dark half's classes start with a `d`, so a piece can carry its
+    // light and dark classes side by side: `pt3 dpt7`.
+    fn with_dark(mut self, theme: &'a Theme) -> CssGen<'a> {
+        let mut dark = CssGen::from(theme, self.config);
+        dark.used = self.used.clone();
+        dark.is_dark = true;
+
+        let table = dark.style_table.replace(BTreeMap::new());
+        *dark.style_table.borrow_mut() = table.into_iter()
+            .map(|(css, class)| (css, format!("d{}", class)))
+            .collect();
+
+        self.dark = Some(Box::new(dark));
+        self
+    }
+
+    fn class_name(&self, class: &str) -> String {
+        if self.is_dark { format!("d{}", class) } else { String::from(class) }
+    }
+
+    // Runs `rules` for the light half and, for light/dark output, adds the
+    // colors from the dark half. Token rules from one theme don't cover
+    // everything the other sets, so they can't be stacked: each theme's only
+    // apply in its own mode. The dark rules apply when the OS asks for a dark
+    // scheme (unless <html> has `.paint-light`) or when an ancestor has
+    // `.paint-dark`, and the light ones the other way around.
+    fn themed<F: Fn(&CssGen) -> String>(&self, rules: F) -> String {
+        let light = rules(self);
+
+        let dark = match self.dark {
+            Some(ref dark) => dark,
+            None => return light,
+        };
+
+        let prefix = &self.config.page_prefix;
+        let light_rules = self.token_rules();
+        let dark_rules = format!("{}{}", color_rules(&rules(dark)), dark.token_rules());
+
+        format!("{}\n@media (prefers-color-scheme: light) {{\n{}}}\n{}@media (prefers-color-scheme: dark) {{\n{}}}\n{}",
+            light,
+            scope_rules(&light_rules, &format!(":root:not(.{}-dark)", prefix)),
+            scope_rules(&light_rules, &format!(".{}-light", prefix)),
+            scope_rules(&dark_rules, &format!(":root:not(.{}-light)", prefix)),
+            scope_rules(&dark_rules, &format!(".{}-dark", prefix)))
+    }
+
+    // rules for the classes tokens get, the ones a theme's colors live in
+    fn token_rules(&self) -> String {
+        if self.config.class_style == ClassStyle::Scopes {
+            return self.scope_class_rules();
+        }
+
+        let prefix = &self.config.css_prefix;
+        let mut out = String::new();
+
+        let font_styles = [
+            ("un", "text-decoration: underline;"),
+            ("bo", "font-weight: bold;"),
+            ("it", "font-style: italic;"),
+        ];
+
+        for &(class, css) in &font_styles {
+            let class = self.class_name(class);
+            if self.is_used(&class) {
+                writeln!(out, ".{} .{} {{ {} }}", prefix, class, css).unwrap();
+            }
+        }
+
+        for (css, class) in self.style_table.borrow().iter().filter(|&(_, class)| self.is_used(class)) {
+            if self.uses_vars() {
+                let property = css.split(':').next().unwrap();
+                writeln!(out, ".{} .{} {{ {}: var(--{}-{}); }}", prefix, class, property, self.config.page_prefix, class).unwrap();
+            } else {
+                writeln!(out, ".{} .{} {{ {} }}", prefix, class, css).unwrap();
+            }
+        }
+
+        out
+    }
+
     fn base(&self) -> String {
         let prefix = &self.config.css_prefix;
         let div = self.outer_div();
         let table = self.table();
         let td = self.td();
-        let ln = self.line_numbers();
-        let hi = self.highlight();
+        let all = !self.config.css_used_only;
 
-        // using pseudo ::after for line numbers, this prevents them
-        // from being copy-paste-able. (which would be annoying)
         let mut out = collapse_whitespace(3, format!(r#"
             .{prefix} {{
                 {}
@@ -143,61 +332,301 @@ impl<'a> CssGen<'a> {
             .{prefix} td {{
                 {}
             }}
-            .{prefix} .ln {{
+        "#, div, table, td, prefix=prefix));
+
+        // the template's last line is only indentation
+        out.truncate(out.trim_end().len());
+        out.push('\n');
+
+        // using pseudo ::after for line numbers, this prevents them
+        // from being copy-paste-able. (which would be annoying)
+        if all || self.config.numbers {
+            let ln = collapse_whitespace(4, format!(r#"
+                .{prefix} .ln {{
+                    {}
+                }}
+                .{prefix} .ln::after {{
+                    content: attr(data-ln);
+                }}
+            "#, self.line_numbers(), prefix=prefix));
+
+            writeln!(out, "{}", ln.trim()).unwrap();
+        }
+
+        if all || !self.config.highlighted.is_empty() {
+            writeln!(out, ".{} .hi {{ {} }}", prefix, self.highlight()).unwrap();
+        }
+
+        if all || !self.config.marks.is_empty() {
+            writeln!(out, ".{} .mk {{ {} }}", prefix, self.mark()).unwrap();
+        }
+
+        // light/dark token rules are added by `themed`, see there
+        if self.dark.is_none() && !self.is_dark {
+            out.push_str(&self.token_rules());
+        }
+
+        if self.config.blame.is_some() {
+            out.push_str(&self.blame_rules());
+        }
+
+        if self.config.callouts || !self.config.annotations.is_empty() {
+            out.push_str(&self.note_rules());
+        }
+
+        if self.config.anchors {
+            out.push_str(&self.anchor_rules());
+        }
+
+        if self.config.copy || self.config.raw {
+            out.push_str(&self.tool_rules());
+        }
+
+        if self.config.fold != Fold::Off {
+            out.push_str(&self.fold_rules());
+        }
+
+        if self.config.wrap {
+            out.push_str(&self.wrap_rules());
+        }
+
+        if self.uses_vars() {
+            out = format!("{}{}", self.vars(), out);
+        }
+
+        out
+    }
+
+    fn scope_class_rules(&self) -> String {
+        let prefix = &self.config.css_prefix;
+        let mut out = String::new();
+
+        for (selectors, css) in scopes::rules(self.theme) {
+            let selectors = selectors.iter()
+                .filter(|sel| !self.config.css_used_only || self.used.borrow().iter().any(|c| sel.matches(c)))
+                .map(|sel| format!(".{} {}", prefix, sel.css()))
+                .collect::<Vec<_>>();
+
+            if !selectors.is_empty() {
+                writeln!(out, "{} {{ {} }}", selectors.join(", "), css).unwrap();
+            }
+        }
+
+        out
+    }
+
+    // whether rules for `class` need to be written, which is always unless
+    // only writing rules for classes that made it into the html
+    fn is_used(&self, class: &str) -> bool {
+        !self.config.css_used_only || self.used.borrow().contains(class)
+    }
+
+    fn uses_vars(&self) -> bool {
+        self.config.css_vars && !self.config.inline
+    }
+
+    // a theme color, or a reference to the custom property holding it
+    fn var(&self, name: &str, value: &Color) -> String {
+        if self.uses_vars() {
+            format!("var(--{}-{})", self.config.page_prefix, name)
+        } else {
+            color::css(value)
+        }
+    }
+
+    // Custom properties for the chrome and every token color, so they can be
+    // overridden without touching the generated rules
+    fn vars(&self) -> String {
+        let prefix = &self.config.css_prefix;
+        let mut vars = vec![
+            (String::from("fg"), color::css(&self.fg)),
+            (String::from("bg"), color::css(&self.bg)),
+            (String::from("header"), color::css(&self.header)),
+            (String::from("border"), color::css(&self.border)),
+            (String::from("highlight"), color::css(&self.highlight)),
+            (String::from("ln"), color::css(&self.line_numbers)),
+            (String::from("muted"), color::css(&self.muted())),
+            (String::from("info"), color::css(&self.info())),
+            (String::from("divider"), color::css(&self.divider())),
+            (String::from("fold"), color::css(&self.fold_background())),
+            (String::from("mark"), color::css(&self.mark_color())),
+            (String::from("added"), color::css(&self.diff_colors().0)),
+            (String::from("removed"), color::css(&self.diff_colors().1)),
+            (String::from("added-change"), color::css(&self.diff_change_colors().0)),
+            (String::from("removed-change"), color::css(&self.diff_change_colors().1)),
+        ];
+
+        if self.config.blame.is_some() {
+            for bucket in 0..5 {
+                vars.push((format!("age{}", bucket), color::css(&self.blame_age(bucket))));
+            }
+        }
+
+        if self.config.class_style == ClassStyle::Theme {
+            for (css, class) in self.style_table.borrow().iter().filter(|&(_, class)| self.is_used(class)) {
+                let value = css.split_once(':').unwrap().1.trim().trim_end_matches(';');
+                vars.push((class.clone(), String::from(value)));
+            }
+        }
+
+        let mut out = format!(".{}, .{}-bordered {{\n", prefix, prefix);
+
+        for (name, value) in vars {
+            writeln!(out, "    --{}-{}: {};", self.config.page_prefix, name, value).unwrap();
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    // text that should recede a little: diff hunk headers, callout badges
+    fn muted(&self) -> Color {
+        color::alpha(&self.fg, 0.6)
+    }
+
+    // header & footer text
+    fn info(&self) -> Color {
+        color::alpha(&self.fg, 0.75)
+    }
+
+    fn divider(&self) -> Color {
+        color::alpha(&self.fg, 0.10)
+    }
+
+    fn fold_background(&self) -> Color {
+        color::alpha(&self.fg, 0.05)
+    }
+
+    // recently changed lines get a brighter bar next to the blame info
+    fn blame_age(&self, bucket: usize) -> Color {
+        let heat = Color { r: 249, g: 130, b: 108, a: 255 }; // #f9826c
+        color::alpha(&heat, 1.0 - (bucket as f32 * 0.2))
+    }
+
+    fn blame(&self, bucket: usize) -> String {
+        collapse_whitespace(2, format!("\
+            max-width: 250px;
+            overflow: hidden;
+            text-overflow: ellipsis;
+            border-right: 2px solid {};
+            -webkit-user-select: none;
+            -moz-user-select: none;
+            -ms-user-select: none;
+            user-select: none;
+            color: {}; \
+        ", self.var(&format!("age{}", bucket), &self.blame_age(bucket)), self.var("ln", &self.line_numbers)))
+    }
+
+    fn blame_rules(&self) -> String {
+        let prefix = &self.config.css_prefix;
+        let mut out = collapse_whitespace(3, format!("
+            .{prefix} .blame {{
                 {}
             }}
-            .{prefix} .ln::after {{
-                content: attr(data-ln);
-            }}
-            .{prefix} .hi {{
-                {}
+            .{prefix} .blame::after {{
+                content: attr(data-blame);
             }}
-            .{prefix} .un {{ text-decoration: underline; }}
-            .{prefix} .bo {{ font-weight: bold; }}
-            .{prefix} .it {{ font-style: italic; }}
-        "#, div, table, td, ln, hi, prefix=prefix));
+        ", self.blame(4), prefix = prefix));
 
-        for (css, class) in &self.style_table {
-            write!(out, ".{} .{} {{ {} }}\n", prefix, class, css).unwrap();;
+        for bucket in 0..4 {
+            writeln!(out, ".{} .blame.age{} {{ border-right-color: {}; }}",
+                prefix, bucket, self.var(&format!("age{}", bucket), &self.blame_age(bucket))).unwrap();
         }
 
         out
     }
 
-    fn style(&self, style: &Style) -> Vec<(String, String)> {
+    fn scoped(&self, style: Style, classes: String) -> Look {
+        let mut scope_classes = self.scope_classes.borrow_mut();
+
+        let i = match scope_classes.iter().position(|c| *c == classes) {
+            Some(i) => i,
+            None => {
+                scope_classes.push(classes);
+                scope_classes.len() - 1
+            }
+        };
+
+        Look { style, dark: None, scope: Some(i) }
+    }
+
+    fn style(&self, look: &Look) -> Vec<(String, String)> {
+        let styles = self.look_styles(look);
+
+        for (class, _) in &styles {
+            self.used.borrow_mut().insert(class.clone());
+        }
+
+        styles
+    }
+
+    // The theme's scope list doesn't cover everything the highlighter can come
+    // up with (blended colors, the default foreground on a scope background...),
+    // so anything new gets a class instead of going missing. It's named after
+    // the css, since which styles turn up depends on the code: a number would
+    // mean something different in each block sharing the theme's stylesheet.
+    fn table_class(&self, css: &str) -> String {
+        if let Some(class) = self.style_table.borrow().get(css) {
+            return class.clone();
+        }
+
+        let mut table = self.style_table.borrow_mut();
+        let class = self.class_name(&hashed_class(css, |class| {
+            table.values().any(|c| *c == self.class_name(class))
+        }));
+
+        eprintln!("note: '{}' isn't one of the styles listed in theme '{}', using class {} for it",
+            css, self.theme.name.as_ref().map_or("", |n| n.as_str()), class);
+
+        table.insert(String::from(css), class.clone());
+        class
+    }
+
+    fn look_styles(&self, look: &Look) -> Vec<(String, String)> {
+        if let Some(i) = look.scope {
+            let classes = self.scope_classes.borrow()[i].clone();
+            return if classes.is_empty() { Vec::new() } else { vec![(classes, String::new())] };
+        }
+
+        let mut styles = self.plain_styles(&look.style);
+
+        if let (Some(dark), Some(dark_gen)) = (look.dark, self.dark.as_ref()) {
+            styles.extend(dark_gen.plain_styles(&dark));
+        }
+
+        styles
+    }
+
+    fn plain_styles(&self, style: &Style) -> Vec<(String, String)> {
         let mut styles = Vec::new();
 
         if style.foreground != self.fg {
             let css = format!("color: {};", color::css(&style.foreground));
-            let class = self.style_table.get(&css).unwrap();
-
-            styles.push((class.clone(), css));
+            styles.push((self.table_class(&css), css));
         }
 
         if style.background != self.bg {
             let css = format!("background: {};", color::css(&style.background));
-            let class = self.style_table.get(&css).unwrap();
-
-            styles.push((class.clone(), css));
+            styles.push((self.table_class(&css), css));
         }
 
         if style.font_style.contains(FontStyle::UNDERLINE) {
             styles.push((
-                String::from("un"),
+                self.class_name("un"),
                 String::from("text-decoration: underline;")
             ));
         }
 
         if style.font_style.contains(FontStyle::BOLD) {
             styles.push((
-                String::from("bo"),
+                self.class_name("bo"),
                 String::from("font-weight: bold;")
             ));
         }
 
         if style.font_style.contains(FontStyle::ITALIC) {
             styles.push((
-                String::from("it"),
+                self.class_name("it"),
                 String::from("font-style: italic;")
             ));
         }
@@ -206,8 +635,8 @@ impl<'a> CssGen<'a> {
     }
 
     fn outer_div(&self) -> String {
-        let fg = color::css(&self.fg);
-        let bg = color::css(&self.bg);
+        let fg = self.var("fg", &self.fg);
+        let bg = self.var("bg", &self.bg);
 
         collapse_whitespace(2, format!("\
             display: block;
@@ -220,31 +649,81 @@ impl<'a> CssGen<'a> {
         ", fg, bg))
     }
 
+    fn font_family(&self) -> &str {
+        self.config.font_family.as_ref().map_or(MONO_FONTS, |f| f.as_str())
+    }
+
+    fn header_font(&self) -> &str {
+        self.config.header_font.as_ref().map_or(SANS_FONTS, |f| f.as_str())
+    }
+
+    fn font_size(&self) -> &str {
+        self.config.font_size.as_ref().map_or("12px", |s| s.as_str())
+    }
+
+    fn line_height(&self) -> &str {
+        self.config.line_height.as_ref().map_or("20px", |s| s.as_str())
+    }
+
     fn table(&self) -> String {
-        collapse_whitespace(2, String::from("\
+        collapse_whitespace(2, format!("\
             width: 100%;
             border-spacing: 0;
             border-collapse: separate;
-            font-family: SFMono-Regular, Consolas, \"Liberation Mono\", Menlo, monospace;
-            font-size: 12px;
-            line-height: 20px;
-            tab-size: 4;
+            font-family: {};
+            font-size: {};
+            line-height: {};
+            tab-size: {};
             color: inherit;
             -webkit-text-size-adjust: 100%;
             -moz-text-size-adjust: 100%;
             -ms-text-size-adjust: 100%;
             text-rendering: optimizeLegibility;
-        "))
+        ", self.font_family(), self.font_size(), self.line_height(), self.config.tab_width))
+    }
+
+    fn padding(&self) -> usize {
+        if self.config.numbers { 10 } else { 13 }
     }
 
-    fn td(&self) -> &'static str {
-        if self.config.numbers {
-            "padding: 0 10px; white-space: pre;"
+    fn td(&self) -> String {
+        if self.config.wrap {
+            format!("padding: 0 {}px; white-space: pre-wrap; overflow-wrap: anywhere; vertical-align: top;",
+                self.padding())
         } else {
-            "padding: 0 13px; white-space: pre;"
+            format!("padding: 0 {}px; white-space: pre;", self.padding())
         }
     }
 
+    // wrapped lines continue at the same indentation as the line's code,
+    // instead of back at the left edge
+    fn hanging(&self, indent: usize) -> String {
+        format!("padding-left: calc({}px + {}ch); text-indent: -{}ch;",
+            self.padding(), indent, indent)
+    }
+
+    fn wrap_rules(&self) -> String {
+        collapse_whitespace(3, format!("
+            .{prefix} tr:not(.fold) > td:last-child {{
+                padding-left: calc({pad}px + var(--indent, 0ch));
+                text-indent: calc(-1 * var(--indent, 0ch));
+                position: relative;
+            }}
+            .{prefix} tr:not(.fold) > td:last-child::before {{
+                content: '';
+                position: absolute;
+                top: {top};
+                bottom: 0;
+                left: {marker}px;
+                border-left: 2px dotted {};
+            }}
+        ", self.var("ln", &self.line_numbers),
+           pad = self.padding(),
+           marker = self.padding() / 2,
+           top = self.line_height(),
+           prefix = self.config.css_prefix))
+    }
+
     fn line_numbers(&self) -> String {
         collapse_whitespace(2, format!("\
             width: 1px;
@@ -256,238 +735,1350 @@ impl<'a> CssGen<'a> {
             -ms-user-select: none;
             user-select: none;
             color: {}; \
-        ", color::css(&self.line_numbers)))
+        ", self.var("ln", &self.line_numbers)))
     }
 
     fn highlight(&self) -> String {
-        format!("background-color: {};", color::css(&self.highlight))
+        format!("background-color: {};", self.var("highlight", &self.highlight))
     }
 
-    fn bordered(&self) -> String {
-        let rest = self.base();
+    // row backgrounds for added / removed lines. Dark themes get translucent
+    // versions so they blend with whatever the background is
+    fn diff_colors(&self) -> (Color, Color) {
+        if color::is_light(&self.bg) {
+            (
+                Color { r: 230, g: 255, b: 237, a: 255 }, // #e6ffed
+                Color { r: 255, g: 238, b: 240, a: 255 }, // #ffeef0
+            )
+        } else {
+            (
+                Color { r: 46, g: 160, b: 67, a: 51 },
+                Color { r: 248, g: 81, b: 73, a: 51 },
+            )
+        }
+    }
 
-        let border = color::css(&self.border);
-        let background = color::css(&self.header);
-        let color = color::css(&color::alpha(&self.fg, 0.75));
-        let divider = color::css(&color::alpha(&self.fg, 0.10));
+    // stronger versions of the above, for the characters that changed
+    fn diff_change_colors(&self) -> (Color, Color) {
+        if color::is_light(&self.bg) {
+            (
+                Color { r: 172, g: 242, b: 189, a: 255 }, // #acf2bd
+                Color { r: 253, g: 184, b: 192, a: 255 }, // #fdb8c0
+            )
+        } else {
+            (
+                Color { r: 46, g: 160, b: 67, a: 115 },
+                Color { r: 248, g: 81, b: 73, a: 115 },
+            )
+        }
+    }
 
-        collapse_whitespace(3, format!("\
-            .{prefix}-bordered {{
-                border: 1px solid {border};
-                border-radius: 2px;
+    fn added_change(&self) -> String {
+        format!("background-color: {};", self.var("added-change", &self.diff_change_colors().0))
+    }
+
+    fn removed_change(&self) -> String {
+        format!("background-color: {};", self.var("removed-change", &self.diff_change_colors().1))
+    }
+
+    fn empty(&self) -> String {
+        format!("background-color: {};", self.var("header", &self.header))
+    }
+
+    fn added(&self) -> String {
+        format!("background-color: {};", self.var("added", &self.diff_colors().0))
+    }
+
+    fn removed(&self) -> String {
+        format!("background-color: {};", self.var("removed", &self.diff_colors().1))
+    }
+
+    fn sign(&self) -> String {
+        format!("width: 1px; padding-right: 0; user-select: none; color: {};",
+            self.var("ln", &self.line_numbers))
+    }
+
+    fn hunk(&self) -> String {
+        format!("color: {}; background-color: {};",
+            self.var("muted", &self.muted()),
+            self.var("header", &self.header))
+    }
+
+    fn diff(&self) -> String {
+        collapse_whitespace(3, format!("
+            .{prefix}-bordered + .{prefix}-bordered {{
+                margin-top: 15px;
             }}
-            .{prefix}-bordered .info {{
-                display: flex;
-                justify-content: space-between;
-                color: {color};
-                background: {background};
-                margin: 0;
-                padding: 10px 15px 10px;
-                font-size: 12px;
-                font-family: SFMono-Regular, Consolas, \"Liberation Mono\", Menlo, monospace;
-                line-height: 1.2;
+            .{prefix} .add td {{ {} }}
+            .{prefix} .del td {{ {} }}
+            .{prefix} .hunk td {{ {} }}
+            .{prefix} .sign {{ {} }}
+            .{prefix} .sign::after {{
+                content: attr(data-sign);
             }}
-            .{prefix}-bordered .info.header {{
-                border-bottom: 1px solid {border};
+        ", self.added(), self.removed(), self.hunk(), self.sign(),
+           prefix = self.config.css_prefix))
+    }
+
+    fn compare(&self) -> String {
+        collapse_whitespace(3, format!("
+            .{prefix} td.add {{ {} }}
+            .{prefix} td.del {{ {} }}
+            .{prefix} td.add .ch {{ {} }}
+            .{prefix} td.del .ch {{ {} }}
+            .{prefix} td.empty {{ {} }}
+            .{prefix} td.code {{ width: 50%; }}
+        ", self.added(), self.removed(), self.added_change(), self.removed_change(),
+           self.empty(), prefix = self.config.css_prefix))
+    }
+
+    // numbered badge at the end of an annotated line
+    fn callout(&self) -> String {
+        collapse_whitespace(2, format!("\
+            display: inline-block;
+            min-width: 16px;
+            height: 16px;
+            margin-left: 8px;
+            padding: 0 4px;
+            box-sizing: border-box;
+            border-radius: 8px;
+            font-size: 10px;
+            line-height: 16px;
+            text-align: center;
+            vertical-align: text-bottom;
+            -webkit-user-select: none;
+            -moz-user-select: none;
+            -ms-user-select: none;
+            user-select: none;
+            color: {};
+            background-color: {}; \
+        ", self.var("bg", &self.bg), self.var("muted", &self.muted())))
+    }
+
+    fn notes(&self) -> String {
+        collapse_whitespace(2, format!("\
+            margin: 10px 0 0;
+            padding: 10px 15px 0 40px;
+            border-top: 1px solid {};
+            font-size: 13px;
+            line-height: 20px;
+            font-family: {};\
+        ", self.var("divider", &self.divider()), self.header_font()))
+    }
+
+    fn note_rules(&self) -> String {
+        collapse_whitespace(3, format!("
+            .{prefix} .co {{
+                {}
             }}
-            .{prefix}-bordered .info.footer {{
-                border-top: 1px solid {border};
+            .{prefix} .co::after {{
+                content: attr(data-co);
             }}
-            .{prefix}-bordered .info .left {{
-                font-weight: 500;
-                font-family: -apple-system, BlinkMacSystemFont, \"Segoe UI\", Roboto, Helvetica, Arial, sans-serif;
+            .{prefix} .notes {{
+                {}
             }}
-            .{prefix}-bordered .info .right span:not(:last-child) {{
-                padding-right: 10px;
-                margin-right: 10px;
+        ", self.callout(), self.notes(), prefix = self.config.css_prefix))
+    }
+
+    fn anchor_rules(&self) -> String {
+        collapse_whitespace(3, format!("
+            .{prefix} .ln a {{
+                display: block;
+                color: inherit;
+                text-decoration: none;
+                cursor: pointer;
+            }}
+            .{prefix} .ln a:hover {{
+                color: {};
+            }}
+            .{prefix} .ln a::after {{
+                content: attr(data-ln);
+            }}
+            .{prefix} tr:target td, .{prefix} tr.sel td {{
+                {}
+            }}
+        ", self.var("fg", &self.fg), self.highlight(), prefix = self.config.css_prefix))
+    }
+
+    // the "⋯ N lines" bar standing in for a collapsed region
+    fn summary(&self) -> String {
+        collapse_whitespace(2, format!("\
+            display: block;
+            list-style: none;
+            padding: 0 10px;
+            cursor: pointer;
+            -webkit-user-select: none;
+            -moz-user-select: none;
+            -ms-user-select: none;
+            user-select: none;
+            color: {};
+            background-color: {}; \
+        ", self.var("ln", &self.line_numbers), self.var("fold", &self.fold_background())))
+    }
+
+    fn fold_rules(&self) -> String {
+        collapse_whitespace(3, format!("
+            .{prefix} tr.fold > td {{
+                padding: 0;
+            }}
+            .{prefix} td.ln {{
+                min-width: max(25px, var(--ln-width, 0px));
+            }}
+            .{prefix} summary {{
+                {}
+            }}
+            .{prefix} summary::-webkit-details-marker {{
+                display: none;
+            }}
+            .{prefix} summary::after {{
+                content: attr(data-closed);
+            }}
+            .{prefix} details[open] > summary::after {{
+                content: attr(data-open);
+            }}
+            .{prefix} summary:hover {{
+                {}
+            }}
+        ", self.summary(), self.highlight(), prefix = self.config.css_prefix))
+    }
+
+    // copy button & raw link, in the header or floating over the code
+    fn tool(&self) -> String {
+        collapse_whitespace(2, format!("\
+            display: inline-block;
+            margin: -3px 0 -3px 6px;
+            padding: 2px 8px;
+            font: inherit;
+            font-size: 11px;
+            line-height: 16px;
+            color: inherit;
+            background: transparent;
+            border: 1px solid {};
+            border-radius: 3px;
+            text-decoration: none;
+            cursor: pointer; \
+        ", self.var("border", &self.border)))
+    }
+
+    fn tools(&self) -> &'static str {
+        "position: absolute; top: 6px; right: 8px; opacity: 0.75;"
+    }
+
+    fn tool_rules(&self) -> String {
+        collapse_whitespace(3, format!("
+            .{prefix} {{
+                position: relative;
+            }}
+            .{prefix}-tools {{
+                {}
+            }}
+            .{prefix}-tool {{
+                {}
+            }}
+        ", self.tools(), self.tool(), prefix = self.config.css_prefix))
+    }
+
+    // marks sit on top of line highlights, so they need to show through them
+    fn mark_color(&self) -> Color {
+        if color::is_light(&self.bg) {
+            Color { r: 255, g: 213, b: 0, a: 102 }
+        } else {
+            Color { r: 255, g: 213, b: 0, a: 64 }
+        }
+    }
+
+    fn mark(&self) -> String {
+        format!("background-color: {}; border-radius: 2px;", self.var("mark", &self.mark_color()))
+    }
+
+    fn bordered(&self) -> String {
+        let rest = self.base();
+
+        let border = self.var("border", &self.border);
+        let background = self.var("header", &self.header);
+        let color = self.var("info", &self.info());
+        let divider = self.var("divider", &self.divider());
+
+        let border_rule = format!(".{}-bordered {{ border: 1px solid {}; border-radius: 2px; }}\n",
+            self.config.css_prefix, border);
+
+        if self.config.css_used_only && !self.config.header && !self.config.footer {
+            return format!("{}{}", border_rule, rest);
+        }
+
+        collapse_whitespace(3, format!("\
+            {border_rule}\
+            .{prefix}-bordered .info {{
+                display: flex;
+                justify-content: space-between;
+                color: {color};
+                background: {background};
+                margin: 0;
+                padding: 10px 15px 10px;
+                font-size: {size};
+                font-family: {mono};
+                line-height: 1.2;
+            }}
+            .{prefix}-bordered .info.header {{
+                border-bottom: 1px solid {border};
+            }}
+            .{prefix}-bordered .info.footer {{
+                border-top: 1px solid {border};
+            }}
+            .{prefix}-bordered .info .left {{
+                font-weight: 500;
+                font-family: {sans};
+            }}
+            .{prefix}-bordered .info .right span:not(:last-child) {{
+                padding-right: 10px;
+                margin-right: 10px;
                 border-right: 1px solid {divider};
             }}
             {rest}\
         ", prefix = self.config.css_prefix,
+           border_rule = border_rule,
            border = border,
            color = color,
            background = background,
            divider = divider,
+           size = self.font_size(),
+           mono = self.font_family(),
+           sans = self.header_font(),
            rest = rest))
     }
 }
 
 
-pub fn css(theme: &Theme, config: &Config) -> String {
-    CssGen::from(theme, config).bordered()
+pub fn css(theme: &Theme, dark: Option<&Theme>, config: &Config) -> String {
+    let mut gen = CssGen::from(theme, config);
+
+    if let Some(dark) = dark {
+        gen = gen.with_dark(dark);
+    }
+
+    gen.themed(|g| g.bordered())
+}
+
+
+// The css `highlight` gives for `config`, without highlighting anything. Only
+// complete with --css-used-only off.
+pub fn stylesheet(theme: &Theme, dark: Option<&Theme>, config: &Config) -> String {
+    let mut gen = CssGen::from(theme, config);
+
+    if let Some(dark) = dark {
+        gen = gen.with_dark(dark);
+    }
+
+    gen.themed(|g| if config.border { g.bordered() } else { g.base() })
+}
+
+
+pub fn highlight(text: &str,
+                 syntax: &SyntaxDefinition,
+                 theme: &Theme,
+                 dark: Option<&Theme>,
+                 config: &Config) -> (String, String) {
+
+    let gen = match dark {
+        Some(dark) => CssGen::from(theme, config).with_dark(dark),
+        None => CssGen::from(theme, config),
+    };
+
+    let (text, callouts) = if config.callouts {
+        annotate::strip_callouts(text)
+    } else {
+        (String::from(text), HashMap::new())
+    };
+
+    let notes = annotate::resolve(&config.annotations, &callouts, config.first_line);
+
+    // buttons go in the header if there is one, otherwise they float over the code
+    let shown = shown_lines(&text, config);
+    let tools = make_tools(&shown.join("\n"), config, &gen);
+    let has_info = config.border && (config.header || config.footer);
+    let (floating, in_info) = if has_info { ("", &tools[..]) } else { (&tools[..], "") };
+
+    let base = make_base_html(&text, syntax, theme, dark, config, &gen, &notes, floating);
+
+    let css = gen.themed(|g| if config.border { g.bordered() } else { g.base() });
+    let html = if config.border { add_border(&base, config, in_info, shown.len()) } else { base };
+
+    (html, css)
+}
+
+
+// Renders each file in a unified diff as its own bordered table. Each side of
+// the diff gets its own highlighter so multi-line constructs keep parsing
+// correctly: context lines go through both, removed lines only through the
+// old side and added lines only through the new side. Each file comes with
+// its tab width, which can differ by language.
+pub fn diff(files: &[(&FileDiff, &SyntaxDefinition, usize)],
+            theme: &Theme,
+            config: &Config) -> (String, String) {
+
+    let gen = CssGen::from(theme, config);
+    let mut html = String::new();
+
+    for &(file, syntax, tab_width) in files {
+        let body = make_diff_html(file, syntax, tab_width, theme, config, &gen);
+        let (added, removed) = file.stats();
+
+        let info = info_bar(
+            "header",
+            &escape_html(file.name()),
+            &format!("<span>+{}</span><span>-{}</span>", added, removed));
+
+        writeln!(html, "<div class='{}-bordered'>{}{}</div>",
+            config.css_prefix, info, body).unwrap();
+    }
+
+    let css = format!("{}\n{}", gen.bordered(), gen.diff());
+
+    (html, css)
+}
+
+
+// Renders two versions of a file next to each other, with matching lines
+// aligned on the same row. Each side is highlighted on its own, and the part
+// of a changed line that actually differs gets an extra `.ch` span.
+pub fn compare(old: (&str, &str),
+               new: (&str, &str),
+               syntax: &SyntaxDefinition,
+               theme: &Theme,
+               config: &Config) -> (String, String) {
+
+    let gen = CssGen::from(theme, config);
+    let old_lines = old.1.lines().collect::<Vec<_>>();
+    let new_lines = new.1.lines().collect::<Vec<_>>();
+
+    let mut painter = Painter::new(syntax, theme, None, config);
+    let old_styled = old_lines.iter().map(|l| painter.paint(l, &gen)).collect::<Vec<_>>();
+
+    let mut painter = Painter::new(syntax, theme, None, config);
+    let new_styled = new_lines.iter().map(|l| painter.paint(l, &gen)).collect::<Vec<_>>();
+
+    let mut body = String::new();
+    let mut added = 0;
+    let mut removed = 0;
+
+    if config.inline {
+        writeln!(body, "<div style='{}'>", gen.outer_div()).unwrap();
+        writeln!(body, "<table style='{}'>", gen.table()).unwrap();
+    } else {
+        write!(body, "<div class='{}'>\n<table>\n", config.css_prefix).unwrap();
+    }
+
+    for (o, n) in diff::align(&old_lines, &new_lines) {
+        let same = match (o, n) {
+            (Some(o), Some(n)) => old_lines[o] == new_lines[n],
+            _ => false,
+        };
+
+        let (old_range, new_range) = match (o, n) {
+            (Some(o), Some(n)) if !same => {
+                let (a, b) = diff::changed_ranges(old_lines[o], new_lines[n]);
+                (vec![a], vec![b])
+            }
+            _ => (Vec::new(), Vec::new()),
+        };
+
+        if !same && o.is_some() { removed += 1; }
+        if !same && n.is_some() { added += 1; }
+
+        let side = |idx: Option<usize>,
+                    styled: &[Vec<(Look, &str)>],
+                    ranges: &[(usize, usize)],
+                    class: &str,
+                    row: &str,
+                    mark: &str| {
+
+            let class = match idx {
+                None => "empty",
+                Some(_) if same => "",
+                Some(_) => class,
+            };
+
+            let row = match idx {
+                None => gen.empty(),
+                Some(_) if same => String::new(),
+                Some(_) => String::from(row),
+            };
+
+            let num = idx.map_or(String::new(), |i| (i + 1).to_string());
+            let ranges = ranges.iter().cloned().filter(|&(a, b)| a < b).collect::<Vec<_>>();
+
+            let mut html = idx.map_or(String::new(), |i| {
+                marked_line_to_html(&styled[i][..], &gen, config.inline, &ranges, "ch", mark)
+            });
+
+            if idx.is_some() && html.is_empty() {
+                html = String::from("&#10;");
+            }
+
+            if config.inline {
+                format!("<td style='{}{}{}'>{}</td><td style='{}{}'>{}</td>",
+                    gen.line_numbers(), gen.td(), row, num, gen.td(), row, html)
+            } else {
+                format!("<td class='ln' data-ln='{}'></td><td class='code {}'>{}</td>",
+                    num, class, html)
+            }
+        };
+
+        writeln!(body, "<tr>{}{}</tr>",
+            side(o, &old_styled, &old_range, "del", &gen.removed(), &gen.removed_change()),
+            side(n, &new_styled, &new_range, "add", &gen.added(), &gen.added_change())).unwrap();
+    }
+
+    body.push_str("</table>\n</div>\n");
+
+    let info = info_bar(
+        "header",
+        &format!("{} &rarr; {}", escape_html(old.0), escape_html(new.0)),
+        &format!("<span>+{}</span><span>-{}</span>", added, removed));
+
+    let html = format!("<div class='{}-bordered'>{}{}</div>", config.css_prefix, info, body);
+    let css = format!("{}\n{}", gen.bordered(), gen.compare());
+
+    (html, css)
+}
+
+
+// FNV-1a, unlike `DefaultHasher` it's promised to give the same answer on
+// every platform and compiler version
+pub fn stable_hash(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+
+// `color: #b31d28;` -> `pt-6e3d0b9f`, the same in every theme that uses it.
+// If another style already has that name, more of the hash is used
+fn hashed_class<F: Fn(&str) -> bool>(css: &str, taken: F) -> String {
+    let hash = format!("{:016x}", stable_hash(css));
+
+    (8..=16).map(|n| format!("pt-{}", &hash[16 - n..]))
+        .chain((2..).map(|n| format!("pt-{}-{}", hash, n)))
+        .find(|class| !taken(class))
+        .unwrap()
+}
+
+
+// Handles links like `#paint-main-rs-L10-L20` for blocks made with anchors:
+// highlights the range and scrolls to it. Clicking a line number selects it,
+// shift-clicking another one in the same block selects the whole range.
+pub fn anchors_script() -> String {
+    collapse_whitespace(2, String::from(r#"
+        (function() {
+            var anchor = null;
+
+            // with --css-inline there's no stylesheet, the table carries the
+            // style for selected rows
+            function inlineStyle(row) {
+                for (var p = row; p && p.getAttribute; p = p.parentNode) {
+                    if (p.hasAttribute('data-sel')) return p.getAttribute('data-sel');
+                }
+                return null;
+            }
+
+            // a line number, linking to the row it's in
+            function lineLink(el) {
+                for (; el && el.tagName; el = el.parentNode) {
+                    if (el.tagName !== 'A') continue;
+                    var row = el.parentNode && el.parentNode.parentNode;
+                    return row && row.id && el.getAttribute('href') === '#' + row.id ? el : null;
+                }
+                return null;
+            }
+
+            function select(hash, scroll) {
+                var old = document.querySelectorAll('tr.sel');
+                for (var i = 0; i < old.length; i++) {
+                    old[i].classList.remove('sel');
+                    if (inlineStyle(old[i]) !== null) old[i].removeAttribute('style');
+                }
+
+                var m = /^#(.+)-L(\d+)(?:-L(\d+))?$/.exec(hash);
+                if (!m) return;
+
+                var from = Math.min(+m[2], +(m[3] || m[2]));
+                var to = Math.max(+m[2], +(m[3] || m[2]));
+                var first = null;
+
+                for (var n = from; n <= to; n++) {
+                    var row = document.getElementById(m[1] + '-L' + n);
+                    if (!row) continue;
+                    row.classList.add('sel');
+                    if (inlineStyle(row) !== null) row.setAttribute('style', inlineStyle(row));
+                    first = first || row;
+
+                    for (var p = row.parentNode; p; p = p.parentNode) {
+                        if (p.tagName === 'DETAILS') p.open = true;
+                    }
+                }
+
+                if (scroll && first) first.scrollIntoView({ block: 'center' });
+            }
+
+            function init() {
+                document.addEventListener('click', function(e) {
+                    var link = lineLink(e.target);
+                    if (!link) return;
+
+                    var m = /^#(.+)-L(\d+)$/.exec(link.getAttribute('href'));
+                    if (!m) return;
+                    e.preventDefault();
+
+                    var hash = '#' + m[1] + '-L' + m[2];
+
+                    if (e.shiftKey && anchor && anchor.block === m[1]) {
+                        var from = Math.min(anchor.line, +m[2]);
+                        var to = Math.max(anchor.line, +m[2]);
+                        hash = '#' + m[1] + '-L' + from + (from === to ? '' : '-L' + to);
+                    } else {
+                        anchor = { block: m[1], line: +m[2] };
+                    }
+
+                    history.replaceState(null, '', hash);
+                    select(hash, false);
+                });
+
+                window.addEventListener('hashchange', function() {
+                    select(location.hash, true);
+                });
+
+                select(location.hash, true);
+            }
+
+            if (document.readyState === 'loading') {
+                document.addEventListener('DOMContentLoaded', init);
+            } else {
+                init();
+            }
+        })();
+    "#))
+}
+
+
+pub fn embed_script(html: &str, css: &str) -> String {
+    format!("document.write('<style scoped>{}</style>');\ndocument.write('{}');",
+        collapse_whitespace(1, escape_js(css)),
+        escape_js(html))
+}
+
+
+pub fn fullpage(html: &str, css: &str, theme: &Theme, dark: Option<&Theme>) -> String {
+    let page_background = |theme: &Theme| {
+        let bg = theme.settings.background.unwrap_or(Color::WHITE);
+        let filter = if !color::is_light(&bg) { "filter: brightness(90%);" } else { "" };
+
+        format!("background-color: {}; {}", color::css(&bg), filter)
+    };
+
+    let background = page_background(theme);
+    let dark_background = dark.map_or(String::new(), |dark| {
+        format!("@media (prefers-color-scheme: dark) {{ div.bg {{ {} }} }}", page_background(dark))
+    });
+
+    collapse_whitespace(2, format!("\
+        <html>
+        <head>
+            <meta name='viewport' content='width=device-width, initial-scale=1'>
+            <style>
+                html, body {{
+                    margin: 0;
+                    padding: 0;
+                }}
+                div.bg {{
+                    position: fixed;
+                    top: 0;
+                    bottom: -100px;
+                    width: 100%;
+                    z-index: -1;
+                    {}
+                }}
+                {}
+                .container {{
+                    max-width: 850px;
+                    margin: 25px auto;
+                    padding: 0 25px;
+                }}
+                {css}
+            </style>
+        </head>
+        <body>
+            <div class='bg'></div>
+            <div class='container'>
+                {html}
+            </div>
+        </body>
+        </html>
+    ", background, dark_background, css = css, html = html))
+}
+
+
+// background, foreground, then the colors the theme gives to code
+fn palette(theme: &Theme) -> Vec<Color> {
+    let mut colors = vec![
+        theme.settings.background.unwrap_or(Color::WHITE),
+        theme.settings.foreground.unwrap_or(Color::BLACK),
+    ];
+
+    for item in theme.scopes.iter().filter(|item| !theme::is_chrome(item)) {
+        if let Some(fore) = item.style.foreground {
+            if !colors.contains(&fore) && colors.len() < 16 {
+                colors.push(fore);
+            }
+        }
+    }
+
+    colors
+}
+
+
+// One page showing a sample in every theme: (name, theme, html, css) for each,
+// each theme's css under its own prefix
+pub fn gallery(cards: &[(String, Theme, String, String)]) -> String {
+    let mut css = String::new();
+    let mut sections = String::new();
+
+    for (name, theme, html, theme_css) in cards {
+        let bg = theme.settings.background.unwrap_or(Color::WHITE);
+        let kind = if color::is_light(&bg) { "light" } else { "dark" };
+
+        let swatches = palette(theme).iter()
+            .map(|c| format!("<span style='background: {}' title='{}'></span>", color::css(c), color::css(c)))
+            .collect::<String>();
+
+        css.push_str(theme_css);
+        write!(sections, "\
+<section class='theme' data-name='{name}' data-kind='{kind}'>
+<h2>{name} <span class='kind'>{kind}</span></h2>
+<div class='swatches'>{}</div>
+{}
+</section>
+", swatches, html, name = escape_html(name), kind = kind).unwrap();
+    }
+
+    collapse_whitespace(2, format!("\
+        <html>
+        <head>
+            <meta name='viewport' content='width=device-width, initial-scale=1'>
+            <title>paint themes</title>
+            <style>
+                html, body {{
+                    margin: 0;
+                    padding: 0;
+                    font-family: {sans};
+                    color: #24292e;
+                    background: #f6f8fa;
+                }}
+                .controls {{
+                    position: sticky;
+                    top: 0;
+                    z-index: 1;
+                    padding: 12px 25px;
+                    background: #ffffff;
+                    border-bottom: 1px solid #dddddd;
+                    font-size: 14px;
+                }}
+                .controls input, .controls select {{
+                    margin-right: 8px;
+                    font-size: 14px;
+                }}
+                .themes {{
+                    display: grid;
+                    grid-template-columns: repeat(auto-fill, minmax(450px, 1fr));
+                    grid-gap: 25px;
+                    max-width: 1200px;
+                    margin: 25px auto;
+                    padding: 0 25px;
+                }}
+                .theme {{
+                    min-width: 0;
+                }}
+                .theme[hidden] {{
+                    display: none;
+                }}
+                .theme h2 {{
+                    margin: 0 0 6px;
+                    font-size: 16px;
+                }}
+                .kind {{
+                    padding: 1px 6px;
+                    font-size: 11px;
+                    font-weight: normal;
+                    border: 1px solid #cccccc;
+                    border-radius: 10px;
+                }}
+                .swatches {{
+                    display: flex;
+                    margin-bottom: 8px;
+                }}
+                .swatches span {{
+                    width: 16px;
+                    height: 16px;
+                    margin-right: 2px;
+                    border: 1px solid rgba(0, 0, 0, 0.15);
+                }}
+                {css}
+            </style>
+        </head>
+        <body>
+            <div class='controls'>
+                <input type='search' id='filter' placeholder='Filter themes'>
+                <select id='kind'>
+                    <option value=''>light & dark</option>
+                    <option value='light'>light</option>
+                    <option value='dark'>dark</option>
+                </select>
+                <span id='count'>{count} themes</span>
+            </div>
+            <div class='themes'>
+                {sections}
+            </div>
+            <script>
+                (function() {{
+                    var filter = document.getElementById('filter');
+                    var kind = document.getElementById('kind');
+                    var themes = document.querySelectorAll('.theme');
+
+                    function update() {{
+                        var text = filter.value.toLowerCase();
+                        var shown = 0;
+
+                        for (var i = 0; i < themes.length; i++) {{
+                            var t = themes[i];
+                            var show = t.getAttribute('data-name').toLowerCase().indexOf(text) !== -1 &&
+                                (!kind.value || t.getAttribute('data-kind') === kind.value);
+
+                            t.hidden = !show;
+                            if (show) shown++;
+                        }}
+
+                        document.getElementById('count').textContent = shown + ' themes';
+                    }}
+
+                    filter.addEventListener('input', update);
+                    kind.addEventListener('change', update);
+                }})();
+            </script>
+        </body>
+        </html>
+    ", sans = SANS_FONTS, css = css, count = cards.len(), sections = sections))
+}
+
+
+fn add_border(body: &str, config: &Config, tools: &str, lines: usize) -> String {
+    let prefix = &config.css_prefix;
+    let class = if config.footer { "footer" } else { "header" };
+
+    if !config.header && !config.footer {
+        return format!("<div class='{}-bordered'>{}</div>", prefix, body);
+    }
+
+    let (left, right) = config.title.as_ref().map_or_else(
+        || {
+            (
+                format!("{}", config.filename),
+                format!("{} lines", lines)
+            )
+        },
+        |title| {
+            let parts = title.split("|").collect::<Vec<_>>();
+
+            (
+                format!("{}", parts[0]),
+                format!("{}", parts.get(1).unwrap_or(&""))
+            )
+        }
+    );
+
+    let right = if tools.is_empty() {
+        right
+    } else {
+        format!("<span>{}</span><span>{}</span>", right, tools)
+    };
+
+    let info = info_bar(class, &left, &right);
+
+    if config.footer {
+        format!("<div class='{}-bordered'>{}{}</div>", prefix, body, info)
+    } else {
+        format!("<div class='{}-bordered'>{}{}</div>", prefix, info, body)
+    }
+}
+
+
+fn info_bar(class: &str, left: &str, right: &str) -> String {
+    format!(r#"
+        <div class="info {}">
+            <span class="left">{}</span>
+            <span class="right">{}</span>
+        </div>
+    "#, class, left, right)
+}
+
+
+fn make_base_html(text: &str,
+                  syntax: &SyntaxDefinition,
+                  theme: &Theme,
+                  dark: Option<&Theme>,
+                  config: &Config,
+                  css_gen: &CssGen,
+                  notes: &Annotations,
+                  tools: &str) -> String {
+
+    let ln = css_gen.line_numbers();
+    let hi = css_gen.highlight();
+    let mk = css_gen.mark();
+    let td = css_gen.td();
+
+    let mut painter = Painter::new(syntax, theme, dark, config);
+    let mut out = String::new();
+    let width = label_width(text.lines().count(), config);
+    let now = git::now();
+    let mut prev_commit: Option<&str> = None;
+
+    // collapsed regions are rows holding a <details> with a table of their own,
+    // so they open and close without any javascript
+    let folds = fold::regions(text, syntax, &config.fold, config.first_line, config.selection, config.tab_width);
+
+    // each of those tables sizes its own columns, so line numbers get the
+    // width of the widest label to keep them lined up with the outer table
+    let (ln, ln_width) = if config.numbers && !folds.is_empty() {
+        (format!("{} min-width: max(25px, {}ch);", ln, width), format!(" style='--ln-width: {}ch;'", width))
+    } else {
+        (ln, String::new())
+    };
+
+    // style for rows the anchors script selects
+    let selected = if config.inline && config.anchors {
+        format!(" data-sel='{}'", css_gen.highlight())
+    } else {
+        String::new()
+    };
+
+    if config.inline && !tools.is_empty() {
+        writeln!(out, "<div style='{} position: relative;'>", css_gen.outer_div()).unwrap();
+        writeln!(out, "<div style='{}'>{}</div>", css_gen.tools(), tools).unwrap();
+        writeln!(out, "<table style='{}'{}>", css_gen.table(), selected).unwrap();
+    } else if config.inline {
+        write!(out, "<div style='{}'>\n", css_gen.outer_div()).unwrap();
+        writeln!(out, "<table style='{}'{}>", css_gen.table(), selected).unwrap();
+    } else if !tools.is_empty() {
+        writeln!(out, "<div class='{}'{}>", config.css_prefix, ln_width).unwrap();
+        write!(out, "<div class='{}-tools'>{}</div>\n<table>\n", config.css_prefix, tools).unwrap();
+    } else {;
+        write!(out, "<div class='{}'{}>\n<table>\n", config.css_prefix, ln_width).unwrap();
+    }
+
+    let mut open_folds: Vec<(usize, usize)> = Vec::new();
+
+    let columns = 1 + config.numbers as usize + config.blame.is_some() as usize;
+
+    for (i, line) in text.lines().enumerate() {
+        let num = config.first_line + i;
+
+        // always pass lines to highlighter so w/e funky regexes it uses
+        // across lines will work, even if we don't show that line
+        let contents = painter.paint(line, css_gen);
+
+        // skip lines not included in user selection (if any)
+        if let Some((start, finish)) = config.selection {
+            if num < start || num > finish { continue; }
+        }
+
+        // mark columns count characters in the text as written
+        let marks = config.marks.get(&num).map_or(Vec::new(), |cols| column_ranges(line, cols));
+
+        // for pages that ignore tab-size, swap tabs for spaces after highlighting
+        // so the highlighter still sees the real text
+        let expanded;
+        let (contents, marks, line) = if config.expand_tabs {
+            expanded = expand_tabs(&contents, config.tab_width);
+            let text = expanded.iter().map(|t| t.1.as_str()).collect::<String>();
+            let marks = marks.iter()
+                .map(|&(start, end)| (expanded_offset(line, start, config.tab_width), expanded_offset(line, end, config.tab_width)))
+                .collect();
+
+            (expanded.iter().map(|&(style, ref t)| (style, t.as_str())).collect(), marks, text)
+        } else {
+            (contents, marks, String::from(line))
+        };
+        let line = &line[..];
+
+        let mut html = marked_line_to_html(&contents[..], css_gen, config.inline, &marks, "mk", &mk);
+
+        // badges use the same ::after trick as line numbers so they
+        // aren't copied along with the code
+        for n in notes.badges.get(&num).map_or(&[][..], |b| &b[..]) {
+            if config.inline {
+                write!(html, "<span style='{}'>{}</span>", css_gen.callout(), n).unwrap();
+            } else {
+                write!(html, "<span class='co' data-co='{}'></span>", n).unwrap();
+            }
+        }
+
+        // `line` never contains a newline char, but we *want* a \n for empty
+        // lines. Otherwise empty lines would collapse row on the table, and
+        // you want newlines to be user copy/paste-able. (Note: '&#10;' vs '\n')
+        if html.is_empty() {
+            html = String::from("&#10;");
+        }
+
+        for &(start, end) in folds.iter().filter(|f| f.0 == i) {
+            let closed = format!("\u{22ef} {} lines", end - start + 1);
+            let open = format!("\u{25be} {} lines", end - start + 1);
+
+            if config.inline {
+                writeln!(out, "<tr><td colspan='{}' style='padding: 0;'><details>\
+                             <summary style='{}'>{}</summary><table style='{}'>",
+                    columns, css_gen.summary(), closed, css_gen.table()).unwrap();
+            } else {
+                writeln!(out, "<tr class='fold'><td colspan='{}'><details>\
+                             <summary data-closed='{}' data-open='{}'></summary><table>",
+                    columns, closed, open).unwrap();
+            }
+
+            open_folds.push((start, end));
+        }
+
+        let label = line_label(num, width, config);
+        let indent = if config.wrap { leading_width(line, config.tab_width) } else { 0 };
+        let anchor = format!("{}-{}-L{}", config.page_prefix, config.id, num);
+
+        if config.anchors {
+            write!(out, "<tr id='{}'>", anchor).unwrap();
+        } else {
+            out.push_str("<tr>");
+        }
+
+        // only the first line of a run from the same commit gets the info
+        if let Some(info) = config.blame.as_ref().and_then(|b| b.get(i)) {
+            let (age, bucket) = git::age(info.time, now);
+
+            let text = if prev_commit == Some(&info.sha) {
+                String::new()
+            } else if info.is_committed() {
+                escape_html(&format!("{}  {}  {}", info.short_sha(), info.author, age))
+            } else {
+                String::from("uncommitted")
+            };
+
+            if config.inline {
+                write!(out, "<td style='{}{}'>{}</td>", css_gen.blame(bucket), td, text).unwrap();
+            } else {
+                write!(out, "<td class='blame age{}' data-blame='{}'></td>", bucket, text).unwrap();
+            }
+
+            prev_commit = Some(&info.sha);
+        }
+
+        if config.inline {
+            if config.numbers && config.anchors {
+                write!(out, "<td style='{}{}'><a href='#{}' style='color: inherit; text-decoration: none;'>{}</a></td>",
+                    ln, td, anchor, label).unwrap();
+            } else if config.numbers {
+                write!(out, "<td style='{}{}'>{}</td>", ln, td, label).unwrap();
+            }
+
+            let hang = if indent > 0 { css_gen.hanging(indent) } else { String::new() };
+
+            if config.highlighted.contains(&num) {
+                write!(out, "<td style='{}{}{}'>{}</td>", hi, td, hang, html).unwrap();
+            } else {
+                write!(out, "<td style='{}{}'>{}</td>", td, hang, html).unwrap();
+            }
+        } else {
+            if config.numbers && config.anchors {
+                write!(out, "<td class='ln'><a href='#{}' data-ln='{}'></a></td>", anchor, label).unwrap();
+            } else if config.numbers {
+                write!(out, "<td class='ln' data-ln='{}'></td>", label).unwrap();
+            }
+
+            let hang = if indent > 0 { format!(" style='--indent: {}ch'", indent) } else { String::new() };
+
+            if config.highlighted.contains(&num) {
+                write!(out, "<td class='hi'{}>{}</td>", hang, html).unwrap();
+            } else {
+                write!(out, "<td{}>{}</td>", hang, html).unwrap();
+            }
+        }
+
+        out.push_str("</tr>\n");
+
+        while open_folds.last().is_some_and(|f| f.1 == i) {
+            out.push_str("</table></details></td></tr>\n");
+            open_folds.pop();
+        }
+    }
+
+    out.push_str("</table>\n");
+
+    if !notes.notes.is_empty() {
+        if config.inline {
+            writeln!(out, "<ol style='{}'>", css_gen.notes()).unwrap();
+        } else {
+            out.push_str("<ol class='notes'>\n");
+        }
+
+        for &(n, ref text) in &notes.notes {
+            writeln!(out, "<li value='{}'>{}</li>", n, escape_html(text)).unwrap();
+        }
+
+        out.push_str("</ol>\n");
+    }
+
+    out.push_str("</div>\n");
+    out
+}
+
+
+// the lines that are actually shown, in plain text
+fn shown_lines<'t>(text: &'t str, config: &Config) -> Vec<&'t str> {
+    text.lines()
+        .enumerate()
+        .filter(|&(i, _)| match config.selection {
+            Some((start, finish)) => config.first_line + i >= start && config.first_line + i <= finish,
+            None => true,
+        })
+        .map(|(_, line)| line)
+        .collect()
+}
+
+
+// Copy button and raw link. The source rides along in an attribute, so line
+// numbers, badges, and lines outside the selection never get copied.
+fn make_tools(source: &str, config: &Config, css_gen: &CssGen) -> String {
+    let mut out = String::new();
+
+    let attr = if config.inline {
+        format!("style='{}'", css_gen.tool())
+    } else {
+        format!("class='{}-tool'", config.css_prefix)
+    };
+
+    if config.copy {
+        let onclick = "var b = this; navigator.clipboard.writeText(b.getAttribute(\"data-source\")).then(function() { \
+                       b.textContent = \"Copied\"; setTimeout(function() { b.textContent = \"Copy\"; }, 1500); });";
+
+        write!(out, "<button {} data-source='{}' onclick='{}'>Copy</button>",
+            attr,
+            escape_html(source).replace('\n', "&#10;"),
+            onclick).unwrap();
+    }
+
+    if config.raw {
+        write!(out, "<a {} href='data:text/plain;charset=utf-8,{}' download='{}'>Raw</a>",
+            attr,
+            percent_encode(source),
+            escape_html(&config.filename)).unwrap();
+    }
+
+    out
+}
+
+
+// width of a line's leading whitespace in characters, tabs going out to the
+// next tab stop
+pub fn leading_width(line: &str, tab_width: usize) -> usize {
+    let tab_width = cmp::max(tab_width, 1);
+
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .fold(0, |col, c| if c == '\t' { col + tab_width - col % tab_width } else { col + 1 })
 }
 
 
-pub fn highlight(text: &str,
-                 syntax: &SyntaxDefinition,
-                 theme: &Theme,
-                 config: &Config) -> (String, String) {
+// where byte `offset` of `line` ends up once `expand_tabs` has run
+fn expanded_offset(line: &str, offset: usize, tab_width: usize) -> usize {
+    let tab_width = cmp::max(tab_width, 1);
+    let mut col = 0;
+    let mut bytes = 0;
 
-    let gen = CssGen::from(theme, config);
-    let base = make_base_html(text, syntax, theme, config, &gen);
+    for (i, ch) in line.char_indices() {
+        if i >= offset { break; }
 
-    let css = if config.border { gen.bordered() } else { gen.base() };
-    let html = if config.border { add_border(&base, config) } else { base };
+        if ch == '\t' {
+            let spaces = tab_width - (col % tab_width);
+            col += spaces;
+            bytes += spaces;
+        } else {
+            col += 1;
+            bytes += ch.len_utf8();
+        }
+    }
 
-    (html, css)
+    bytes
 }
 
 
-pub fn embed_script(html: &str, css: &str) -> String {
-    format!("document.write('<style scoped>{}</style>');\ndocument.write('{}');",
-        collapse_whitespace(1, escape_js(css)),
-        escape_js(html))
+// tabs -> spaces, out to the next tab stop. Columns carry over from piece to
+// piece since a tab's width depends on everything before it on the line.
+fn expand_tabs(v: &[(Look, &str)], tab_width: usize) -> Vec<(Look, String)> {
+    let tab_width = cmp::max(tab_width, 1);
+    let mut col = 0;
+
+    v.iter().map(|&(style, text)| {
+        let mut out = String::new();
+
+        for ch in text.chars() {
+            if ch == '\t' {
+                let spaces = tab_width - (col % tab_width);
+                out.push_str(&" ".repeat(spaces));
+                col += spaces;
+            } else {
+                out.push(ch);
+                col += 1;
+            }
+        }
+
+        (style, out)
+    }).collect()
 }
 
 
-pub fn fullpage(html: &str, css: &str, theme: &Theme) -> String {
-    let bg = theme.settings.background.unwrap_or(Color::WHITE);
-    let background = color::css(&bg);
-    let filter = if !color::is_light(&bg) { "filter: brightness(90%)" } else { "" };
+// 1-based, inclusive character columns -> byte ranges into `line`
+fn column_ranges(line: &str, cols: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let offset = |col: usize| {
+        line.char_indices()
+            .nth(col)
+            .map_or(line.len(), |(i, _)| i)
+    };
 
-    collapse_whitespace(2, format!("\
-        <html>
-        <head>
-            <meta name='viewport' content='width=device-width, initial-scale=1'>
-            <style>
-                html, body {{
-                    margin: 0;
-                    padding: 0;
-                }}
-                div.bg {{
-                    position: fixed;
-                    top: 0;
-                    bottom: -100px;
-                    width: 100%;
-                    z-index: -1;
-                    background-color: {};
-                    {}
-                }}
-                .container {{
-                    max-width: 850px;
-                    margin: 25px auto;
-                    padding: 0 25px;
-                }}
-                {css}
-            </style>
-        </head>
-        <body>
-            <div class='bg'></div>
-            <div class='container'>
-                {html}
-            </div>
-        </body>
-        </html>
-    ", background, filter, css = css, html = html))
+    cols.iter()
+        .map(|&(start, end)| (offset(start.saturating_sub(1)), offset(end)))
+        .filter(|&(start, end)| start < end)
+        .collect()
 }
 
 
-fn add_border(body: &str, config: &Config) -> String {
-    let prefix = &config.css_prefix;
-    let class = if config.footer { "footer" } else { "header" };
+// number shown in the gutter for line `num`, which may be blank if we are
+// only labeling every nth line, relative to a focus line, or padded out
+fn line_label(num: usize, width: usize, config: &Config) -> String {
+    let value = match config.relative {
+        Some(focus) if num != focus => num.abs_diff(focus),
+        _ => num,
+    };
 
-    if !config.header && !config.footer {
-        return format!("<div class='{}-bordered'>{}</div>", prefix, body);
+    let is_focus = config.relative == Some(num);
+    let every = cmp::max(config.every, 1);
+
+    if !is_focus && value % every != 0 {
+        return String::new();
     }
 
-    let (left, right) = config.title.as_ref().map_or_else(
-        || {
-            (
-                format!("{}", config.filename),
-                format!("{} lines", body.matches("<tr>").count())
-            )
-        },
-        |title| {
-            let parts = title.split("|").collect::<Vec<_>>();
+    match config.number_pad {
+        Some(pad) => {
+            let digits = value.to_string();
+            let fill = width.saturating_sub(digits.len());
 
-            (
-                format!("{}", parts[0]),
-                format!("{}", parts.get(1).unwrap_or(&""))
-            )
+            format!("{}{}", pad.to_string().repeat(fill), digits)
         }
-    );
+        None => value.to_string(),
+    }
+}
 
-    let info = format!(r#"
-        <div class="info {}">
-            <span class="left">{}</span>
-            <span class="right">{}</span>
-        </div>
-    "#, class, left, right);
 
-    if config.footer {
-        format!("<div class='{}-bordered'>{}{}</div>", prefix, body, info)
-    } else {
-        format!("<div class='{}-bordered'>{}{}</div>", prefix, info, body)
-    }
+// widest label we'll need, so padded numbers all line up
+fn label_width(count: usize, config: &Config) -> usize {
+    let last = config.first_line + count.saturating_sub(1);
+    let widest = match config.selection {
+        Some((_, finish)) => cmp::min(last, finish),
+        None => last,
+    };
+
+    widest.to_string().len()
 }
 
 
-fn make_base_html(text: &str,
+fn make_diff_html(file: &FileDiff,
                   syntax: &SyntaxDefinition,
+                  tab_width: usize,
                   theme: &Theme,
                   config: &Config,
                   css_gen: &CssGen) -> String {
 
-    let ln = css_gen.line_numbers();
-    let hi = css_gen.highlight();
     let td = css_gen.td();
+    let ln = css_gen.line_numbers();
+    let sign = css_gen.sign();
 
-    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut old_side = Painter::new(syntax, theme, None, config);
+    let mut new_side = Painter::new(syntax, theme, None, config);
     let mut out = String::new();
-    let mut num = 0;
+
+    // the stylesheet has the default tab width
+    let tab_size = if tab_width == config.tab_width {
+        String::new()
+    } else {
+        format!("tab-size: {};", tab_width)
+    };
 
     if config.inline {
-        write!(out, "<div style='{}'>\n", css_gen.outer_div()).unwrap();
-        write!(out, "<table style='{}'>\n", css_gen.table()).unwrap();
-    } else {;
+        writeln!(out, "<div style='{}'>", css_gen.outer_div()).unwrap();
+        writeln!(out, "<table style='{}{}'>", css_gen.table(), tab_size).unwrap();
+    } else if tab_size.is_empty() {
         write!(out, "<div class='{}'>\n<table>\n", config.css_prefix).unwrap();
+    } else {
+        write!(out, "<div class='{}'>\n<table style='{}'>\n", config.css_prefix, tab_size).unwrap();
     }
 
-    for line in text.lines() {
-        num += 1;
-
-        // always pass lines to highlighter so w/e funky regexes it uses
-        // across lines will work, even if we don't show that line
-        let contents = highlighter.highlight(&line);
-        let mut html = line_to_html(&contents[..], css_gen, config.inline);
-
-        // skip lines not included in user selection (if any)
-        if let Some((start, finish)) = config.selection {
-            if num < start || num > finish { continue; }
+    for hunk in &file.hunks {
+        if config.inline {
+            writeln!(out, "<tr><td colspan='4' style='{}{}'>{}</td></tr>",
+                css_gen.hunk(), td, escape_html(&hunk.header)).unwrap();
+        } else {
+            writeln!(out, "<tr class='hunk'><td colspan='4'>{}</td></tr>",
+                escape_html(&hunk.header)).unwrap();
         }
 
-        // `line` never contains a newline char, but we *want* a \n for empty
-        // lines. Otherwise empty lines would collapse row on the table, and
-        // you want newlines to be user copy/paste-able. (Note: '&#10;' vs '\n')
-        if html.is_empty() {
-            html = String::from("&#10;");
-        }
+        for line in &hunk.lines {
+            let (class, mark, row) = match line.kind {
+                Kind::Context => {
+                    old_side.paint(&line.text, css_gen);
+                    ("", " ", String::new())
+                }
+                Kind::Added => ("add", "+", css_gen.added()),
+                Kind::Removed => ("del", "-", css_gen.removed()),
+            };
 
-        out.push_str("<tr>");
+            let contents = if line.kind == Kind::Removed {
+                old_side.paint(&line.text, css_gen)
+            } else {
+                new_side.paint(&line.text, css_gen)
+            };
 
-        if config.inline {
-            if config.numbers {
-                write!(out, "<td style='{}{}'>{}</td>", ln, td, num).unwrap();
-            }
+            let mut html = line_to_html(&contents[..], css_gen, config.inline);
 
-            if config.highlighted.contains(&num) {
-                write!(out, "<td style='{}{}'>{}</td>", hi, td, html).unwrap();
-            } else {
-                write!(out, "<td style='{}'>{}</td>", td, html).unwrap();
-            }
-        } else {
-            if config.numbers {
-                write!(out, "<td class='ln' data-ln='{}'></td>", num).unwrap();
+            if html.is_empty() {
+                html = String::from("&#10;");
             }
 
-            if config.highlighted.contains(&num) {
-                write!(out, "<td class='hi'>{}</td>", html).unwrap();
+            let old = line.old.map_or(String::new(), |n| n.to_string());
+            let new = line.new.map_or(String::new(), |n| n.to_string());
+
+            if config.inline {
+                writeln!(out, "<tr><td style='{ln}{td}{row}'>{}</td><td style='{ln}{td}{row}'>{}</td>\
+                             <td style='{sign}{row}'>{}</td><td style='{td}{row}'>{}</td></tr>",
+                    old, new, mark, html, ln = ln, td = td, sign = sign, row = row).unwrap();
             } else {
-                write!(out, "<td>{}</td>", html).unwrap();
+                let class = if class.is_empty() {
+                    String::new()
+                } else {
+                    format!(" class='{}'", class)
+                };
+
+                writeln!(out, "<tr{}><td class='ln' data-ln='{}'></td><td class='ln' data-ln='{}'></td>\
+                             <td class='sign' data-sign='{}'></td><td>{}</td></tr>",
+                    class, old, new, mark, html).unwrap();
             }
         }
-
-        out.push_str("</tr>\n");
     }
 
     out.push_str("</table>\n</div>\n");
@@ -495,11 +2086,113 @@ fn make_base_html(text: &str,
 }
 
 
-fn line_to_html(v: &[(Style, &str)], css_gen: &CssGen, inline: bool) -> String {
+fn looks(v: Vec<(Style, &str)>) -> Vec<(Look, &str)> {
+    v.into_iter().map(|(style, text)| (Look::from(style), text)).collect()
+}
+
+
+// Lines up the pieces from the light and dark highlighters. Each theme splits
+// the line wherever its own styles change, so pieces are cut at the edges
+// from either side.
+fn pair_looks<'b>(light: &[(Style, &'b str)], dark: &[(Style, &'b str)]) -> Vec<(Look, &'b str)> {
+    let mut out = Vec::new();
+    let mut dark = dark.iter().cloned();
+    let mut dark_left: Option<(Style, &str)> = None;
+
+    for &(style, mut text) in light {
+        while !text.is_empty() {
+            let (dark_style, dark_text) = match dark_left.take().or_else(|| dark.next()) {
+                Some(piece) => piece,
+                None => break,
+            };
+
+            let n = cmp::min(text.len(), dark_text.len());
+            out.push((Look { style, dark: Some(dark_style), scope: None }, &text[..n]));
+
+            if n < dark_text.len() {
+                dark_left = Some((dark_style, &dark_text[n..]));
+            }
+
+            text = &text[n..];
+        }
+    }
+
+    out
+}
+
+
+fn line_to_html(v: &[(Look, &str)], css_gen: &CssGen, inline: bool) -> String {
+    marked_line_to_html(v, css_gen, inline, &[], "", "")
+}
+
+
+// Split highlighted pieces wherever a mark starts or ends, flagging each piece
+// as inside a mark or not. `marks` are byte ranges into the whole line.
+fn split_at_marks<'b>(v: &[(Look, &'b str)], marks: &[(usize, usize)])
+    -> Vec<(Look, &'b str, bool)>
+{
+    let mut pieces = Vec::new();
+    let mut pos = 0;
+
+    for &(style, text) in v.iter() {
+        let end = pos + text.len();
+
+        let mut cuts = marks.iter()
+                            .flat_map(|&(a, b)| vec![a, b])
+                            .filter(|&c| c > pos && c < end && text.is_char_boundary(c - pos))
+                            .collect::<Vec<_>>();
+
+        cuts.sort();
+        cuts.dedup();
+        cuts.push(end);
+
+        let mut from = pos;
+
+        for cut in cuts {
+            let marked = marks.iter().any(|&(a, b)| from >= a && from < b);
+            pieces.push((style, &text[from - pos..cut - pos], marked));
+            from = cut;
+        }
+
+        pos = end;
+    }
+
+    pieces
+}
+
+
+// Like `line_to_html`, but also wraps the byte ranges in `marks` with a span
+// of their own. Syntax spans are closed and reopened around the edges of each
+// mark, so the nesting is always valid html.
+fn marked_line_to_html(v: &[(Look, &str)],
+                       css_gen: &CssGen,
+                       inline: bool,
+                       marks: &[(usize, usize)],
+                       mark_class: &str,
+                       mark_style: &str) -> String {
+
     let mut out = String::new();
-    let mut prev_style: Option<&Style> = None;
+    let mut prev_style: Option<Look> = None;
+    let mut in_mark = false;
+
+    for (style, text, marked) in split_at_marks(v, marks) {
+        if marked != in_mark {
+            if prev_style.is_some() {
+                write!(out, "</span>").unwrap();
+                prev_style = None;
+            }
+
+            if in_mark {
+                write!(out, "</span>").unwrap();
+            } else if inline {
+                write!(out, "<span style='{}'>", mark_style).unwrap();
+            } else {
+                write!(out, "<span class='{}'>", mark_class).unwrap();
+            }
+
+            in_mark = marked;
+        }
 
-    for &(ref style, text) in v.iter() {
         let unify_style = if let Some(ps) = prev_style {
             style == ps
         } else {
@@ -522,9 +2215,9 @@ fn line_to_html(v: &[(Style, &str)], css_gen: &CssGen, inline: bool) -> String {
                 css.push(style.1);
             }
 
-            prev_style = if !css.is_empty() { Some(style) } else { None };
+            prev_style = if !classes.is_empty() { Some(style) } else { None };
 
-            if css.is_empty() {
+            if classes.is_empty() {
                 write!(out, "{}", html).unwrap();
             } else if inline {
                 write!(out, "<span style='{}'>{}", css.join(" "), html).unwrap();
@@ -538,6 +2231,10 @@ fn line_to_html(v: &[(Style, &str)], css_gen: &CssGen, inline: bool) -> String {
         write!(out, "</span>").unwrap();
     }
 
+    if in_mark {
+        write!(out, "</span>").unwrap();
+    }
+
     out
 }
 
@@ -608,6 +2305,201 @@ fn escape_js(text: &str) -> String {
 }
 
 
+// for data uris, everything but unreserved characters gets encoded
+fn percent_encode(text: &str) -> String {
+    let mut out = String::new();
+
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char);
+            }
+            _ => write!(out, "%{:02X}", byte).unwrap(),
+        }
+    }
+
+    out
+}
+
+
+// puts `ancestor` in front of every selector in a (flat) stylesheet
+fn scope_rules(css: &str, ancestor: &str) -> String {
+    let mut out = String::new();
+
+    for rule in css.split('}').filter(|r| !r.trim().is_empty()) {
+        let mut parts = rule.splitn(2, '{');
+        let selectors = parts.next().unwrap();
+        let body = parts.next().unwrap_or("");
+
+        let scoped = selectors.split(',')
+            .map(|sel| format!("{} {}", ancestor, sel.trim()))
+            .collect::<Vec<_>>();
+
+        writeln!(out, "{} {{{}}}", scoped.join(", "), body).unwrap();
+    }
+
+    out
+}
+
+
+// Only the declarations that set a color, for the dark half of a light/dark
+// stylesheet: layout and fonts are the same in both
+fn color_rules(css: &str) -> String {
+    let is_color = |property: &str| {
+        property.starts_with("--") ||
+            property == "color" ||
+            property.starts_with("background") ||
+            (property.starts_with("border") && !["radius", "spacing", "collapse", "width", "style"].iter().any(|p| property.contains(p)))
+    };
+
+    let mut out = String::new();
+
+    for rule in css.split('}').filter(|r| !r.trim().is_empty()) {
+        let mut parts = rule.splitn(2, '{');
+        let selectors = parts.next().unwrap().trim();
+        let body = parts.next().unwrap_or("");
+
+        let declarations = body.split(';')
+            .map(|d| d.trim())
+            .filter(|d| is_color(d.split(':').next().unwrap().trim()))
+            .collect::<Vec<_>>();
+
+        if !declarations.is_empty() {
+            writeln!(out, "{} {{ {}; }}", selectors, declarations.join("; ")).unwrap();
+        }
+    }
+
+    out
+}
+
+
+// A stylesheet's rules as (@media, selector, body, text), with whitespace
+// collapsed in all but the text
+fn css_rules(css: &str) -> Vec<(String, String, String, String)> {
+    let squash = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let mut rules = Vec::new();
+    let mut media = String::new();
+    let mut rest = css;
+
+    loop {
+        rest = rest.trim_start();
+
+        if rest.starts_with('}') {
+            media.clear();
+            rest = &rest[1..];
+            continue;
+        }
+
+        let open = match rest.find('{') {
+            Some(i) => i,
+            None => break,
+        };
+
+        let head = squash(&rest[..open]);
+
+        if head.starts_with('@') {
+            media = head;
+            rest = &rest[open + 1..];
+            continue;
+        }
+
+        let close = rest[open..].find('}').map_or(rest.len() - 1, |i| open + i);
+        let body = squash(&rest[open + 1..close]);
+
+        rules.push((media.clone(), head, body, String::from(&rest[..close + 1])));
+        rest = &rest[close + 1..];
+    }
+
+    rules
+}
+
+
+// Whether `css` gives a selector in `sheet` a different body
+pub fn css_conflicts(sheet: &str, css: &str) -> bool {
+    let existing = css_rules(sheet);
+
+    css_rules(css).iter().any(|(media, selector, body, _)| {
+        let same = existing.iter().filter(|r| r.0 == *media && r.1 == *selector);
+        let mut bodies = same.map(|r| &r.2).peekable();
+        bodies.peek().is_some() && !bodies.any(|b| b == body)
+    })
+}
+
+
+// `sheet` plus the rules from `css` it doesn't have yet, each one after the
+// rule it follows in `css` so the cascade stays the same. A rule that only
+// adds declarations to one in `sheet`, like the custom properties with
+// --css-used-only, is folded into it.
+pub fn merge_css(sheet: &str, css: &str) -> String {
+    let declarations = |body: &str| body.split(';')
+        .map(|d| d.trim())
+        .filter(|d| !d.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    let property = |d: &str| String::from(d.split(':').next().unwrap().trim());
+
+    let mut rules = css_rules(sheet);
+    let mut at = 0;
+
+    for rule in css_rules(css) {
+        if let Some(i) = rules.iter().position(|r| r.0 == rule.0 && r.1 == rule.1 && r.2 == rule.2) {
+            at = i + 1;
+            continue;
+        }
+
+        let new = declarations(&rule.2);
+
+        let extends = rules.iter().position(|r| {
+            let old = declarations(&r.2);
+            r.0 == rule.0 && r.1 == rule.1 && new.iter().all(|d| {
+                old.contains(d) || !old.iter().any(|o| property(o) == property(d))
+            })
+        });
+
+        match extends {
+            Some(i) => {
+                let mut body = declarations(&rules[i].2);
+                body.extend(new.into_iter().filter(|d| !body.contains(d)).collect::<Vec<_>>());
+
+                let text = &rules[i].3;
+                let selector = text[..text.find('{').unwrap()].trim_end();
+                rules[i].3 = if text.contains('\n') {
+                    format!("{} {{\n{}}}", selector, body.iter().map(|d| format!("    {};\n", d)).collect::<String>())
+                } else {
+                    format!("{} {{ {}; }}", selector, body.join("; "))
+                };
+                rules[i].2 = body.iter().map(|d| format!("{};", d)).collect::<Vec<_>>().join(" ");
+
+                at = i + 1;
+            }
+            None => {
+                rules.insert(at, rule);
+                at += 1;
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let mut media = "";
+
+    for (rule_media, _, _, text) in &rules {
+        if rule_media != media {
+            if !media.is_empty() { out.push_str("}\n"); }
+            if !rule_media.is_empty() { writeln!(out, "{} {{", rule_media).unwrap(); }
+            media = rule_media;
+        }
+
+        writeln!(out, "{}", text).unwrap();
+    }
+
+    if !media.is_empty() { out.push_str("}\n"); }
+
+    out
+}
+
+
 fn collapse_whitespace(n: usize, text: String) -> String {
     let indent = String::from("    ").repeat(n);
 
@@ -626,3 +2518,22 @@ fn human_readable(filesize: usize) -> String {
 
     format!("{:.2} {}", size, unit)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashed_classes_grow_on_a_clash() {
+        let css = "color: #b31d28;";
+        let short = hashed_class(css, |_| false);
+        assert_eq!(short.len(), "pt-".len() + 8);
+
+        let long = hashed_class(css, |class| class == short);
+        assert!(long.starts_with("pt-") && long.ends_with(&short["pt-".len()..]));
+        assert_eq!(long.len(), short.len() + 1);
+
+        assert!(hashed_class(css, |class| !class.ends_with("-2")).ends_with("-2"));
+    }
+}
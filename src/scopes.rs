@@ -0,0 +1,271 @@
+// Class names taken from TextMate scopes instead of colors, so the same
+// markup works with any theme's stylesheet. A piece of code gets one class
+// per atom of every scope it's in, ex: `string.quoted.double.rust` inside
+// `source.rust` -> `s-source s-rust s-string s-quoted s-double`.
+//
+// Theme selectors turn into compound class selectors the same way, so
+// `punctuation.definition.string` beats `string` by having more classes,
+// which is close to how TextMate ranks them.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use serde_json::{self, Value};
+use syntect::highlighting::{Highlighter, Style, Theme, FontStyle, ScopeSelector};
+use syntect::parsing::{ParseState, Scope, ScopeStack, ScopeStackOp, SyntaxDefinition};
+
+use color;
+use theme;
+
+
+const CLASS_PREFIX: &str = "s-";
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassStyle {
+    // `pt1`, `pt2`... numbered by color, different for every theme
+    Theme,
+    // `s-keyword s-control`, the same for every theme
+    Scopes,
+}
+
+
+pub fn parse(setting: &str) -> Option<ClassStyle> {
+    match setting {
+        "theme" => Some(ClassStyle::Theme),
+        "scopes" => Some(ClassStyle::Scopes),
+        _ => None,
+    }
+}
+
+
+// Like `HighlightLines`, but each piece also gets the classes for its scopes
+pub struct ScopeLines<'a> {
+    state: ParseState,
+    stack: ScopeStack,
+    highlighter: Highlighter<'a>,
+}
+
+impl<'a> ScopeLines<'a> {
+    pub fn new(syntax: &SyntaxDefinition, theme: &'a Theme) -> ScopeLines<'a> {
+        ScopeLines {
+            state: ParseState::new(syntax),
+            stack: ScopeStack::new(),
+            highlighter: Highlighter::new(theme),
+        }
+    }
+
+    pub fn highlight<'b>(&mut self, line: &'b str) -> Vec<(Style, String, &'b str)> {
+        let mut ops = self.state.parse_line(line);
+        let mut pieces = Vec::new();
+        let mut pos = 0;
+
+        // the text after the last op still needs a piece
+        ops.push((line.len(), ScopeStackOp::Noop));
+
+        for &(i, ref op) in &ops {
+            if i > pos {
+                let scopes = self.stack.as_slice();
+                let style = self.highlighter.style_for_stack(scopes);
+
+                pieces.push((style, classes(scopes), &line[pos..i]));
+                pos = i;
+            }
+
+            self.stack.apply(op);
+        }
+
+        pieces
+    }
+}
+
+
+fn atoms(scope: &Scope) -> Vec<String> {
+    scope.build_string().split('.').map(String::from).collect()
+}
+
+
+// class attribute for a scope stack, each atom once, outermost first
+pub fn classes(scopes: &[Scope]) -> String {
+    let mut seen: Vec<String> = Vec::new();
+
+    for atom in scopes.iter().flat_map(atoms) {
+        if !seen.contains(&atom) {
+            seen.push(atom);
+        }
+    }
+
+    seen.iter()
+        .map(|atom| format!("{}{}", CLASS_PREFIX, atom))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+
+// atoms can hold characters that mean something in a selector, like `c++`
+fn escape_ident(atom: &str) -> String {
+    let mut out = String::new();
+
+    for ch in atom.chars() {
+        if ch.is_alphanumeric() || ch == '-' || ch == '_' {
+            out.push(ch);
+        } else {
+            out.push('\\');
+            out.push(ch);
+        }
+    }
+
+    out
+}
+
+
+fn compound(atoms: &[String]) -> String {
+    atoms.iter()
+        .map(|atom| format!(".{}{}", CLASS_PREFIX, escape_ident(atom)))
+        .collect()
+}
+
+
+fn split_atoms(scopes: &[String]) -> Vec<String> {
+    scopes.iter()
+        .flat_map(|scope| scope.split('.'))
+        .filter(|atom| !atom.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+
+// A theme selector as atoms. Classes don't keep the nesting order, so a path
+// just needs all of its atoms.
+#[derive(Debug, Clone)]
+pub struct Selector {
+    atoms: Vec<String>,
+    excludes: Vec<Vec<String>>,
+}
+
+impl Selector {
+    // `meta.function entity.name - comment` -> `.s-meta.s-function.s-entity.s-name:not(.s-comment)`
+    pub fn css(&self) -> String {
+        let mut css = compound(&self.atoms);
+
+        for exclude in &self.excludes {
+            write!(css, ":not({})", compound(exclude)).unwrap();
+        }
+
+        css
+    }
+
+    // whether this would apply to a piece with the given class attribute
+    pub fn matches(&self, classes: &str) -> bool {
+        let has = |atom: &String| {
+            classes.split_whitespace().any(|c| c.len() > CLASS_PREFIX.len() && &c[CLASS_PREFIX.len()..] == atom)
+        };
+
+        self.atoms.iter().all(&has) &&
+            !self.excludes.iter().any(|exclude| exclude.iter().all(&has))
+    }
+}
+
+
+// The scopes in a selector, and the ones it excludes. `ScopeSelector` keeps
+// these private, and themes loaded from the bundled dump don't have their
+// original text, so this goes by how syntect 2.0 serializes one:
+//
+//   { "path": { "clear_stack": [], "scopes": ["source", "string"] },
+//     "excludes": [{ "clear_stack": [], "scopes": ["comment"] }] }
+//
+// If that layout changes, the selector won't parse back to itself and this
+// panics instead of quietly giving every rule empty selectors.
+fn selector_scopes(selector: &ScopeSelector) -> (Vec<String>, Vec<Vec<String>>) {
+    let value = serde_json::to_value(selector).unwrap_or(Value::Null);
+
+    let scopes = |stack: &Value| -> Vec<String> {
+        stack["scopes"].as_array()
+            .map_or(Vec::new(), |s| s.iter().filter_map(|s| s.as_str()).map(String::from).collect())
+    };
+
+    let path = scopes(&value["path"]);
+    let excludes = value["excludes"].as_array()
+        .map_or(Vec::new(), |e| e.iter().map(scopes).collect());
+
+    let text = join_selector(&path, &excludes);
+
+    if ScopeSelector::from_str(&text).ok().as_ref() != Some(selector) {
+        panic!("can't read scope selector {:?} (got '{}'), has syntect's serde layout changed?", selector, text);
+    }
+
+    (path, excludes)
+}
+
+
+fn join_selector(path: &[String], excludes: &[Vec<String>]) -> String {
+    let mut out = path.join(" ");
+
+    for exclude in excludes {
+        write!(out, " - {}", exclude.join(" ")).unwrap();
+    }
+
+    out
+}
+
+
+// back to how it's written in a theme, `source string - comment`
+pub fn selector_string(selector: &ScopeSelector) -> String {
+    let (path, excludes) = selector_scopes(selector);
+    join_selector(&path, &excludes)
+}
+
+
+fn to_selector(selector: &ScopeSelector) -> Option<Selector> {
+    let (path, excludes) = selector_scopes(selector);
+
+    if path.is_empty() {
+        return None;
+    }
+
+    Some(Selector {
+        atoms: split_atoms(&path),
+        excludes: excludes.iter().filter(|e| !e.is_empty()).map(|e| split_atoms(e)).collect(),
+    })
+}
+
+
+// (selectors, declarations) for each theme rule, in theme order so later
+// rules win ties like they do in the theme
+pub fn rules(theme: &Theme) -> Vec<(Vec<Selector>, String)> {
+    let mut rules = Vec::new();
+
+    for item in theme.scopes.iter().filter(|item| !theme::is_chrome(item)) {
+        let mut css = Vec::new();
+
+        if let Some(fore) = item.style.foreground {
+            css.push(format!("color: {};", color::css(&fore)));
+        }
+
+        if let Some(back) = item.style.background {
+            css.push(format!("background: {};", color::css(&back)));
+        }
+
+        if let Some(font_style) = item.style.font_style {
+            let has = |style, on, off| if font_style.contains(style) { on } else { off };
+
+            css.push(format!("font-weight: {};", has(FontStyle::BOLD, "bold", "normal")));
+            css.push(format!("font-style: {};", has(FontStyle::ITALIC, "italic", "normal")));
+            css.push(format!("text-decoration: {};", has(FontStyle::UNDERLINE, "underline", "none")));
+        }
+
+        if css.is_empty() {
+            continue;
+        }
+
+        let selectors = item.scope.selectors.iter()
+            .filter_map(to_selector)
+            .collect::<Vec<_>>();
+
+        if !selectors.is_empty() {
+            rules.push((selectors, css.join(" ")));
+        }
+    }
+
+    rules
+}
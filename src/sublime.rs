@@ -0,0 +1,421 @@
+// Sublime Text's .sublime-color-scheme: JSON with `variables`, `globals` and
+// `rules`, where colors can be CSS colors, `var(name)`, or the color-mod
+// function, ex: `color(var(blue) blend(#fff 80%) alpha(0.5))`.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde_json::{self, Value};
+use syntect::highlighting::{Color, StyleModifier, Theme};
+
+use color;
+use theme;
+
+
+pub fn load(path: &Path) -> Result<Theme, String> {
+    let data = theme::read(path)?;
+    let json: Value = serde_json::from_str(&theme::strip_comments(&data))
+        .map_err(|e| format!("problem parsing '{}': {}", path.display(), e))?;
+
+    let variables = json["variables"].as_object()
+        .map(|vars| vars.iter().filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), String::from(v)))).collect())
+        .unwrap_or_default();
+
+    let colors = Colors { variables };
+    let color = |value: &Value| -> Option<Color> {
+        // a list of colors makes a gradient, only the first can be used
+        let text = match *value {
+            Value::String(ref s) => s.as_str(),
+            Value::Array(ref a) => a.first().and_then(|v| v.as_str())?,
+            _ => return None,
+        };
+
+        match colors.parse(text) {
+            Ok(color) => Some(color),
+            Err(e) => {
+                eprintln!("skipping color '{}' in '{}': {}", text, path.display(), e);
+                None
+            }
+        }
+    };
+
+    let mut theme = Theme {
+        name: json["name"].as_str().map(String::from)
+            .or_else(|| path.file_stem().map(|s| s.to_string_lossy().into_owned())),
+        author: json["author"].as_str().map(String::from),
+        ..Theme::default()
+    };
+
+    {
+        let globals = &json["globals"];
+        let settings = &mut theme.settings;
+
+        settings.foreground = color(&globals["foreground"]);
+        settings.background = color(&globals["background"]);
+        settings.caret = color(&globals["caret"]);
+        settings.line_highlight = color(&globals["line_highlight"]);
+        settings.selection = color(&globals["selection"]);
+        settings.selection_border = color(&globals["selection_border"]);
+        settings.gutter = color(&globals["gutter"]);
+        settings.gutter_foreground = color(&globals["gutter_foreground"]);
+        settings.guide = color(&globals["guide"]);
+        settings.find_highlight = color(&globals["find_highlight"]);
+    }
+
+    for rule in json["rules"].as_array().map_or(&[][..], |r| &r[..]) {
+        let scope = match rule["scope"].as_str() {
+            Some(scope) => scope,
+            None => continue,
+        };
+
+        let style = StyleModifier {
+            foreground: color(&rule["foreground"]),
+            background: color(&rule["background"]),
+            font_style: rule["font_style"].as_str().map(theme::parse_font_style),
+        };
+
+        theme::push_rule(&mut theme, scope, style, path);
+    }
+
+    Ok(theme)
+}
+
+
+struct Colors {
+    variables: HashMap<String, String>,
+}
+
+impl Colors {
+    fn parse(&self, text: &str) -> Result<Color, String> {
+        let mut reader = Reader { text, pos: 0 };
+        let color = self.color(&mut reader, 0)?;
+
+        reader.skip_space();
+        if reader.pos < text.len() {
+            return Err(format!("unexpected '{}'", &text[reader.pos..]));
+        }
+
+        Ok(color)
+    }
+
+    fn color(&self, reader: &mut Reader, depth: usize) -> Result<Color, String> {
+        if depth > 20 {
+            return Err(String::from("variables refer to each other in a loop"));
+        }
+
+        reader.skip_space();
+
+        if reader.peek() == Some('#') {
+            let word = reader.word();
+            return color::parse(word).ok_or_else(|| format!("bad hex color '{}'", word));
+        }
+
+        let name = reader.word().to_lowercase();
+
+        if reader.peek() != Some('(') {
+            return named(&name).ok_or_else(|| format!("unknown color '{}'", name));
+        }
+
+        reader.expect('(')?;
+
+        let color = match name.as_str() {
+            "var" => {
+                let var = reader.word();
+                let value = self.variables.get(var).ok_or_else(|| format!("no variable '{}'", var))?;
+                self.color(&mut Reader { text: value, pos: 0 }, depth + 1)?
+            }
+            "rgb" | "rgba" => {
+                let n = reader.numbers()?;
+                if n.len() < 3 { return Err(format!("{}() needs 3 numbers", name)); }
+
+                let channel = |i: usize| {
+                    let (value, percent) = n[i];
+                    let value = if percent { value * 2.55 } else { value };
+                    value.clamp(0.0, 255.0).round() as u8
+                };
+                Color { r: channel(0), g: channel(1), b: channel(2), a: alpha(n.get(3)) }
+            }
+            "hsl" | "hsla" => {
+                let n = reader.numbers()?;
+                if n.len() < 3 { return Err(format!("{}() needs 3 numbers", name)); }
+
+                color::from_hsl(n[0].0 / 360.0, n[1].0 / 100.0, n[2].0 / 100.0, alpha(n.get(3)))
+            }
+            "color" => {
+                let mut color = self.color(reader, depth)?;
+
+                reader.skip_space();
+                while reader.peek() != Some(')') && reader.peek().is_some() {
+                    color = self.adjust(color, reader, depth)?;
+                    reader.skip_space();
+                }
+
+                color
+            }
+            _ => return Err(format!("unknown function '{}()'", name)),
+        };
+
+        reader.expect(')')?;
+        Ok(color)
+    }
+
+    // one of the adjusters in `color(base adjuster...)`
+    fn adjust(&self, base: Color, reader: &mut Reader, depth: usize) -> Result<Color, String> {
+        let name = reader.word().to_lowercase();
+        reader.expect('(')?;
+
+        let color = match name.as_str() {
+            "blend" | "blenda" => {
+                let other = self.color(reader, depth + 1)?;
+                let (amount, percent) = reader.number()?;
+                let amount = if percent { amount / 100.0 } else { amount };
+                color::blend(&base, &other, amount, name == "blenda")
+            }
+            "alpha" | "a" => {
+                let value = reader.change()?;
+                let a = value.apply(base.a as f32 / 255.0, 1.0);
+                Color { a: (a.clamp(0.0, 1.0) * 255.0).round() as u8, ..base }
+            }
+            "lightness" | "l" => {
+                let (h, s, l) = color::to_hsl(&base);
+                color::from_hsl(h, s, reader.change()?.apply(l, 100.0), base.a)
+            }
+            "saturation" | "s" => {
+                let (h, s, l) = color::to_hsl(&base);
+                color::from_hsl(h, reader.change()?.apply(s, 100.0), l, base.a)
+            }
+            // min-contrast() needs the background a color ends up on
+            "min-contrast" => {
+                reader.skip_to(')');
+                base
+            }
+            _ => return Err(format!("unknown adjuster '{}()'", name)),
+        };
+
+        reader.expect(')')?;
+        Ok(color)
+    }
+}
+
+
+// `0.5` or `50%`, the fourth argument to rgba() and hsla()
+fn alpha(value: Option<&(f32, bool)>) -> u8 {
+    match value {
+        Some(&(n, percent)) => {
+            let n = if percent { n / 100.0 } else { n };
+            (n.clamp(0.0, 1.0) * 255.0).round() as u8
+        }
+        None => 255,
+    }
+}
+
+
+// what an adjuster does: set a value, or move it by / scale it by some amount
+enum Change {
+    Set(f32, bool),
+    Add(f32, bool),
+    Scale(f32),
+}
+
+impl Change {
+    // `percent_of` is what 100% means for this value, as a plain number
+    fn apply(&self, value: f32, percent_of: f32) -> f32 {
+        let plain = |n: f32, percent: bool| if percent { n / 100.0 } else { n / percent_of };
+
+        match *self {
+            Change::Set(n, percent) => plain(n, percent),
+            Change::Add(n, percent) => value + plain(n, percent),
+            Change::Scale(n) => value * n,
+        }
+    }
+}
+
+
+struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek().filter(|&c| c.is_whitespace() || c == ',' || c == '/') {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn skip_to(&mut self, end: char) {
+        while self.peek().is_some_and(|c| c != end) {
+            self.pos += self.peek().unwrap().len_utf8();
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_space();
+
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected '{}' at '{}'", c, &self.text[self.pos..]))
+        }
+    }
+
+    // a name, number, or hex color
+    fn word(&mut self) -> &'a str {
+        self.skip_space();
+        let start = self.pos;
+
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || "#-_.%".contains(c)) {
+            self.pos += self.peek().unwrap().len_utf8();
+        }
+
+        &self.text[start..self.pos]
+    }
+
+    // a number, and whether it was a percentage
+    fn number(&mut self) -> Result<(f32, bool), String> {
+        let word = self.word();
+        let percent = word.ends_with('%');
+
+        word.trim_end_matches('%').trim_end_matches("deg").parse::<f32>()
+            .map(|n| (n, percent))
+            .map_err(|_| format!("expected a number, got '{}'", word))
+    }
+
+    fn numbers(&mut self) -> Result<Vec<(f32, bool)>, String> {
+        let mut numbers = Vec::new();
+
+        self.skip_space();
+        while self.peek().is_some_and(|c| c != ')') {
+            numbers.push(self.number()?);
+            self.skip_space();
+        }
+
+        Ok(numbers)
+    }
+
+    // `0.5`, `+ 10%`, `- 5%` or `* 1.2`
+    fn change(&mut self) -> Result<Change, String> {
+        self.skip_space();
+
+        let op = match self.peek() {
+            Some(c) if c == '+' || c == '*' => { self.pos += 1; Some(c) }
+            Some('-') if self.text[self.pos + 1..].starts_with(' ') => { self.pos += 1; Some('-') }
+            _ => None,
+        };
+
+        let (n, percent) = self.number()?;
+
+        Ok(match op {
+            Some('+') => Change::Add(n, percent),
+            Some('-') => Change::Add(-n, percent),
+            Some(_) => Change::Scale(if percent { n / 100.0 } else { n }),
+            None => Change::Set(n, percent),
+        })
+    }
+}
+
+
+// the CSS named colors schemes tend to use
+fn named(name: &str) -> Option<Color> {
+    let hex = match name {
+        "black" => "#000000",
+        "white" => "#ffffff",
+        "red" => "#ff0000",
+        "green" => "#008000",
+        "blue" => "#0000ff",
+        "yellow" => "#ffff00",
+        "orange" => "#ffa500",
+        "purple" => "#800080",
+        "pink" => "#ffc0cb",
+        "gray" | "grey" => "#808080",
+        "silver" => "#c0c0c0",
+        "maroon" => "#800000",
+        "olive" => "#808000",
+        "lime" => "#00ff00",
+        "aqua" | "cyan" => "#00ffff",
+        "teal" => "#008080",
+        "navy" => "#000080",
+        "fuchsia" | "magenta" => "#ff00ff",
+        "transparent" => "#00000000",
+        _ => return None,
+    };
+
+    color::parse(hex)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str, variables: &[(&str, &str)]) -> Result<String, String> {
+        let colors = Colors {
+            variables: variables.iter().map(|&(k, v)| (String::from(k), String::from(v))).collect(),
+        };
+
+        colors.parse(text).map(|c| color::hex(&c))
+    }
+
+    #[test]
+    fn plain_colors() {
+        assert_eq!(parse("#abc", &[]), Ok(String::from("#aabbcc")));
+        assert_eq!(parse("rgb(255, 0, 0)", &[]), Ok(String::from("#ff0000")));
+        assert_eq!(parse("rgba(100%, 0%, 0%, 0.5)", &[]), Ok(String::from("#ff000080")));
+        assert_eq!(parse("white", &[]), Ok(String::from("#ffffff")));
+        assert!(parse("#ff00zz", &[]).is_err());
+    }
+
+    #[test]
+    fn hsl() {
+        assert_eq!(parse("hsl(120, 100%, 25%)", &[]), Ok(String::from("#008000")));
+        assert_eq!(parse("hsla(240deg, 100%, 50%, 50%)", &[]), Ok(String::from("#0000ff80")));
+    }
+
+    #[test]
+    fn variable_chains() {
+        let vars = [("blue", "#0000ff"), ("accent", "var(blue)"), ("link", "var(accent)")];
+
+        assert_eq!(parse("var(link)", &vars), Ok(String::from("#0000ff")));
+        assert_eq!(parse("color(var(link) alpha(0.5))", &vars), Ok(String::from("#0000ff80")));
+        assert!(parse("var(missing)", &vars).is_err());
+    }
+
+    #[test]
+    fn variable_loops() {
+        let vars = [("a", "var(b)"), ("b", "var(a)")];
+        assert!(parse("var(a)", &vars).unwrap_err().contains("loop"));
+    }
+
+    #[test]
+    fn adjusters() {
+        assert_eq!(parse("color(#000000 blend(#ffffff 25%))", &[]), Ok(String::from("#bfbfbf")));
+        assert_eq!(parse("color(#ff0000 alpha(0.5))", &[]), Ok(String::from("#ff000080")));
+        assert_eq!(parse("color(#ff000080 a(* 0.5))", &[]), Ok(String::from("#ff000040")));
+        assert_eq!(parse("color(hsl(0, 100%, 50%) l(+ 5%))", &[]), parse("hsl(0, 100%, 55%)", &[]));
+        assert_eq!(parse("color(hsl(0, 100%, 50%) l(- 10%))", &[]), parse("hsl(0, 100%, 40%)", &[]));
+        assert_eq!(parse("color(#ff0000 min-contrast(#fff 4.5))", &[]), Ok(String::from("#ff0000")));
+        assert!(parse("color(#ff0000 wobble(2))", &[]).is_err());
+    }
+
+    #[test]
+    fn changes() {
+        assert_eq!(Change::Set(50.0, true).apply(0.2, 100.0), 0.5);
+        assert_eq!(Change::Set(0.25, false).apply(0.2, 1.0), 0.25);
+        assert_eq!(Change::Add(-10.0, true).apply(0.5, 100.0), 0.4);
+        assert_eq!(Change::Scale(2.0).apply(0.25, 1.0), 0.5);
+    }
+
+    #[test]
+    fn reader_steps_over_any_whitespace() {
+        let mut reader = Reader { text: "\u{a0}, 12% )", pos: 0 };
+
+        assert_eq!(reader.number(), Ok((12.0, true)));
+        assert_eq!(reader.expect(')'), Ok(()));
+        assert_eq!(parse("rgb(255,\u{a0}0,\u{2003}0)", &[]), Ok(String::from("#ff0000")));
+    }
+}
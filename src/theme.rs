@@ -0,0 +1,490 @@
+// Loading themes from the formats paint understands, and writing them back
+// out as a .tmTheme.
+//
+// Editors keep their own colors for things like the line number gutter, which
+// paint normally works out from the background. A theme can set them with
+// special scopes no syntax produces, so they survive being saved as a .tmTheme:
+//
+//   paint.header        background of the header & footer
+//   paint.border        foreground, color of the border
+//   paint.highlight     background of highlighted lines
+//   paint.line-numbers  foreground, color of line numbers
+
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use syntect::highlighting::{Color, FontStyle, StyleModifier, Theme, ThemeItem, ThemeSet, ScopeSelectors};
+use syntect::parsing::Scope;
+
+use color;
+use scopes;
+use sublime;
+use vscode;
+
+
+pub const HEADER: &str = "paint.header";
+pub const BORDER: &str = "paint.border";
+pub const HIGHLIGHT: &str = "paint.highlight";
+pub const LINE_NUMBERS: &str = "paint.line-numbers";
+
+
+// the extensions `load` knows, besides .tmTheme
+pub const EXTENSIONS: &[&str] = &["tmTheme", "json", "sublime-color-scheme"];
+
+
+// picks a loader by extension, anything unknown is taken as a .tmTheme
+pub fn load(path: &Path) -> Result<Theme, String> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => vscode::load(path),
+        Some("sublime-color-scheme") => sublime::load(path),
+        _ => ThemeSet::get_theme(path).map_err(|e| format!("{:?}", e)),
+    }
+}
+
+
+// theme files in a directory and the ones under it, sorted so the same name
+// always resolves the same way
+pub fn discover(dir: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return found,
+    };
+
+    for path in entries.filter_map(|e| e.ok().map(|e| e.path())) {
+        if path.is_dir() {
+            found.extend(discover(&path));
+        } else if path.extension().is_some_and(|e| EXTENSIONS.iter().any(|ext| e == *ext)) {
+            found.push(path);
+        }
+    }
+
+    found.sort();
+    found
+}
+
+
+pub fn read(path: &Path) -> Result<String, String> {
+    let mut data = String::new();
+
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut data))
+        .map_err(|e| format!("can't read '{}': {}", path.display(), e))?;
+
+    Ok(data)
+}
+
+
+// JSON theme files can have comments and trailing commas
+pub fn strip_comments(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut out = String::new();
+    let mut i = 0;
+    let mut in_string = false;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+
+        if in_string {
+            out.push(c);
+            if c == '\\' {
+                if let Some(n) = next { out.push(n); }
+                i += 1;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+            out.push(c);
+        } else if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' { i += 1; }
+            continue;
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) { i += 1; }
+            i += 2;
+            continue;
+        } else if c == ',' {
+            // dropped if only whitespace (or comments) come before the closing bracket
+            match next_token(&chars, i + 1) {
+                Some('}') | Some(']') => {}
+                _ => out.push(c),
+            }
+        } else {
+            out.push(c);
+        }
+
+        i += 1;
+    }
+
+    out
+}
+
+
+// the next character that isn't whitespace or in a comment
+fn next_token(chars: &[char], mut i: usize) -> Option<char> {
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            (c, _) if c.is_whitespace() => i += 1,
+            ('/', Some(&'/')) => {
+                while i < chars.len() && chars[i] != '\n' { i += 1; }
+            }
+            ('/', Some(&'*')) => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) { i += 1; }
+                i += 2;
+            }
+            (c, _) => return Some(c),
+        }
+    }
+
+    None
+}
+
+
+// Scope selectors like syntect parses them, except for groups. It splits on
+// `,` and `|` even inside parentheses, so groups are expanded first:
+// `source.python (meta.function | meta.class)` becomes
+// `source.python meta.function, source.python meta.class`, and an excluded
+// group `keyword - (source.c | source.c++)` becomes
+// `keyword - source.c - source.c++`.
+pub fn selectors(text: &str) -> Result<ScopeSelectors, String> {
+    let expanded = expand(text)?;
+    ScopeSelectors::from_str(&expanded.join(", ")).map_err(|e| format!("{:?}", e))
+}
+
+
+// each alternative in a selector list, without any groups
+fn expand(text: &str) -> Result<Vec<String>, String> {
+    let mut alternatives = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' | '|' if depth == 0 => {
+                alternatives.extend(expand_selector(&text[start..i])?);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    alternatives.extend(expand_selector(&text[start..])?);
+    Ok(alternatives)
+}
+
+
+// One selector: a path, and the paths after each `-` it excludes. A group
+// in the path makes a selector for each of its alternatives, one in an
+// excluded path excludes each of them.
+fn expand_selector(text: &str) -> Result<Vec<String>, String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut paths = vec![String::new()];
+    let mut excludes: Vec<String> = Vec::new();
+    let mut exclude: Option<Vec<String>> = None;
+    let mut i = 0;
+
+    // `a` + [`b`, `c`] -> [`a b`, `a c`]
+    let append = |to: &[String], parts: &[String]| -> Vec<String> {
+        to.iter()
+            .flat_map(|s| parts.iter().map(move |part| format!("{} {}", s, part).trim().to_string()))
+            .collect()
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        let parts = if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '(' {
+            let mut depth = 0;
+            let start = i + 1;
+
+            while i < chars.len() {
+                match chars[i] {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                i += 1;
+                if depth == 0 { break; }
+            }
+
+            if depth != 0 {
+                return Err(format!("unbalanced parentheses in '{}'", text));
+            }
+
+            expand(&chars[start..i - 1].iter().collect::<String>())?
+        } else if c == ')' {
+            return Err(format!("unbalanced parentheses in '{}'", text));
+        } else {
+            let start = i;
+            while i < chars.len() && !chars[i].is_whitespace() && chars[i] != '(' && chars[i] != ')' {
+                i += 1;
+            }
+
+            let word = chars[start..i].iter().collect::<String>();
+
+            if let Some(rest) = word.strip_prefix('-') {
+                excludes.extend(exclude.take().unwrap_or_default());
+                exclude = Some(vec![String::new()]);
+
+                if rest.is_empty() { continue; }
+                vec![String::from(rest)]
+            } else {
+                vec![word]
+            }
+        };
+
+        match exclude {
+            Some(ref mut exclude) => *exclude = append(exclude, &parts),
+            None => paths = append(&paths, &parts),
+        }
+    }
+
+    excludes.extend(exclude.unwrap_or_default());
+
+    Ok(paths.iter().map(|path| {
+        let mut out = path.clone();
+        for exclude in &excludes {
+            write!(out, " - {}", exclude).unwrap();
+        }
+        out
+    }).collect())
+}
+
+
+// `bold italic` as a FontStyle. The underline flavors all end up as a plain
+// underline, anything a tmTheme can't hold (like `strikethrough`) is dropped.
+pub fn parse_font_style(names: &str) -> FontStyle {
+    let mut style = FontStyle::empty();
+
+    for name in names.split_whitespace() {
+        match name {
+            "bold" => style.insert(FontStyle::BOLD),
+            "italic" => style.insert(FontStyle::ITALIC),
+            "underline" | "stippled_underline" | "squiggly_underline" => style.insert(FontStyle::UNDERLINE),
+            _ => {}
+        }
+    }
+
+    style
+}
+
+
+// adds a rule from a theme file, or says why it can't
+pub fn push_rule(theme: &mut Theme, scope: &str, style: StyleModifier, path: &Path) {
+    match selectors(scope) {
+        Ok(selectors) => theme.scopes.push(ThemeItem { scope: selectors, style }),
+        Err(e) => eprintln!("skipping rule for '{}' in '{}': {}", scope, path.display(), e),
+    }
+}
+
+
+fn chrome_scope(item: &ThemeItem) -> Option<Scope> {
+    if item.scope.selectors.len() != 1 {
+        return None;
+    }
+
+    item.scope.selectors[0].extract_single_scope()
+}
+
+
+pub fn is_chrome(item: &ThemeItem) -> bool {
+    chrome_scope(item).is_some_and(|scope| scope.build_string().starts_with("paint."))
+}
+
+
+// the color a theme sets for one of the chrome scopes above
+pub fn chrome(theme: &Theme, name: &str) -> Option<Color> {
+    let scope = Scope::new(name).ok()?;
+
+    // the last one wins
+    theme.scopes.iter()
+        .rev()
+        .filter(|item| chrome_scope(item) == Some(scope))
+        .find_map(|item| if name == HEADER || name == HIGHLIGHT {
+            item.style.background
+        } else {
+            item.style.foreground
+        })
+}
+
+
+pub fn chrome_item(name: &str, color: Color) -> ThemeItem {
+    let mut style = StyleModifier::default();
+
+    if name == HEADER || name == HIGHLIGHT {
+        style.background = Some(color);
+    } else {
+        style.foreground = Some(color);
+    }
+
+    ThemeItem {
+        scope: ScopeSelectors::from_str(name).unwrap(),
+        style,
+    }
+}
+
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+
+// a rule's scope and settings, spelled the way theme files have them
+pub fn rule_entries(item: &ThemeItem) -> (String, Vec<(&'static str, String)>) {
+    let scope = item.scope.selectors.iter()
+        .map(scopes::selector_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut style = Vec::new();
+    if let Some(fore) = item.style.foreground { style.push(("foreground", color::hex(&fore))); }
+    if let Some(back) = item.style.background { style.push(("background", color::hex(&back))); }
+    if let Some(font) = item.style.font_style { style.push(("fontStyle", font_style(font))); }
+
+    (scope, style)
+}
+
+
+fn font_style(style: FontStyle) -> String {
+    let mut names = Vec::new();
+
+    if style.contains(FontStyle::BOLD) { names.push("bold"); }
+    if style.contains(FontStyle::ITALIC) { names.push("italic"); }
+    if style.contains(FontStyle::UNDERLINE) { names.push("underline"); }
+
+    names.join(" ")
+}
+
+
+fn write_entries(out: &mut String, entries: &[(&str, String)], indent: &str) {
+    for &(key, ref value) in entries {
+        write!(out, "{}<key>{}</key>\n{}<string>{}</string>\n", indent, key, indent, escape_xml(value)).unwrap();
+    }
+}
+
+
+// A .tmTheme plist with the colors paint (and syntect) know about
+pub fn to_tmtheme(theme: &Theme) -> String {
+    let settings = &theme.settings;
+    let mut global = Vec::new();
+
+    {
+        let mut color = |key, value: Option<Color>| {
+            if let Some(value) = value {
+                global.push((key, color::hex(&value)));
+            }
+        };
+
+        color("foreground", settings.foreground);
+        color("background", settings.background);
+        color("caret", settings.caret);
+        color("lineHighlight", settings.line_highlight);
+        color("selection", settings.selection);
+        color("selectionBorder", settings.selection_border);
+        color("gutter", settings.gutter);
+        color("gutterForeground", settings.gutter_foreground);
+        color("guide", settings.guide);
+        color("findHighlight", settings.find_highlight);
+    }
+
+    let mut out = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+"#);
+
+    let mut info = Vec::new();
+    if let Some(ref name) = theme.name { info.push(("name", name.clone())); }
+    if let Some(ref author) = theme.author { info.push(("author", author.clone())); }
+    write_entries(&mut out, &info, "\t");
+
+    out.push_str("\t<key>settings</key>\n\t<array>\n");
+    out.push_str("\t\t<dict>\n\t\t\t<key>settings</key>\n\t\t\t<dict>\n");
+    write_entries(&mut out, &global, "\t\t\t\t");
+    out.push_str("\t\t\t</dict>\n\t\t</dict>\n");
+
+    for item in &theme.scopes {
+        let (scope, style) = rule_entries(item);
+
+        out.push_str("\t\t<dict>\n");
+        write_entries(&mut out, &[("scope", scope)], "\t\t\t");
+        out.push_str("\t\t\t<key>settings</key>\n\t\t\t<dict>\n");
+        write_entries(&mut out, &style, "\t\t\t\t");
+        out.push_str("\t\t\t</dict>\n\t\t</dict>\n");
+    }
+
+    out.push_str("\t</array>\n</dict>\n</plist>\n");
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_become_alternatives() {
+        assert_eq!(expand("source.python (meta.function | meta.class)").unwrap(),
+                   vec!["source.python meta.function", "source.python meta.class"]);
+
+        assert_eq!(expand("(a | b) (c, d)").unwrap(), vec!["a c", "a d", "b c", "b d"]);
+        assert_eq!(expand("a, b | c").unwrap(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn excluded_groups() {
+        assert_eq!(expand("keyword - (source.c | source.c++)").unwrap(),
+                   vec!["keyword - source.c - source.c++"]);
+
+        assert_eq!(expand("(string | comment) - meta.tag").unwrap(),
+                   vec!["string - meta.tag", "comment - meta.tag"]);
+
+        assert_eq!(expand("text -markup.raw").unwrap(), vec!["text - markup.raw"]);
+    }
+
+    #[test]
+    fn unbalanced_groups() {
+        assert!(expand("source (meta.function").is_err());
+        assert!(expand("source meta.function)").is_err());
+    }
+
+    #[test]
+    fn selectors_parse_like_syntect() {
+        assert_eq!(selectors("string, comment - meta.tag").unwrap(),
+                   ScopeSelectors::from_str("string, comment - meta.tag").unwrap());
+
+        assert_eq!(selectors("source (string | comment)").unwrap(),
+                   ScopeSelectors::from_str("source string, source comment").unwrap());
+    }
+
+    #[test]
+    fn comments_and_trailing_commas() {
+        let json = r#"{
+            // a comment
+            "a": [1, 2, /* three */ ],
+            "b": "http://example.com", /* done */
+        }"#;
+
+        let stripped = strip_comments(json);
+        assert_eq!(stripped.split_whitespace().collect::<String>(), r#"{"a":[1,2],"b":"http://example.com"}"#);
+    }
+
+    #[test]
+    fn commas_and_slashes_in_strings() {
+        assert_eq!(strip_comments(r#"{"a": "x, }", "b": "\"//\""}"#), r#"{"a": "x, }", "b": "\"//\""}"#);
+    }
+}
@@ -0,0 +1,183 @@
+// VS Code color themes: JSON (with comments) holding `tokenColors`, the
+// TextMate rules, and `colors` for the editor itself. A theme can `include`
+// another one and override parts of it, and `tokenColors` can name a
+// .tmTheme file to take the rules from instead.
+
+use std::path::Path;
+
+use serde_json::{self, Map, Value};
+use syntect::highlighting::{StyleModifier, Theme, ThemeSet};
+
+use color;
+use theme;
+
+
+pub fn load(path: &Path) -> Result<Theme, String> {
+    let json = read_json(path, 0)?;
+
+    if !json["tokenColors"].is_array() {
+        return Err(format!("'{}' has no tokenColors, is it a VS Code color theme?", path.display()));
+    }
+
+    let colors = |key: &str| json["colors"][key].as_str().and_then(color::parse);
+    let mut theme = Theme {
+        name: json["name"].as_str().map(String::from)
+            .or_else(|| path.file_stem().map(|s| s.to_string_lossy().into_owned())),
+        author: json["author"].as_str().map(String::from),
+        ..Theme::default()
+    };
+
+    {
+        let settings = &mut theme.settings;
+        settings.foreground = colors("editor.foreground");
+        settings.background = colors("editor.background");
+        settings.caret = colors("editorCursor.foreground");
+        settings.line_highlight = colors("editor.lineHighlightBackground");
+        settings.selection = colors("editor.selectionBackground");
+        settings.gutter = colors("editorGutter.background");
+        settings.gutter_foreground = colors("editorLineNumber.foreground");
+        settings.guide = colors("editorIndentGuide.background");
+        settings.find_highlight = colors("editor.findMatchHighlightBackground");
+    }
+
+    for rule in json["tokenColors"].as_array().unwrap() {
+        let style = style_modifier(&rule["settings"]);
+
+        let scope = match rule["scope"] {
+            Value::String(ref scope) => scope.clone(),
+            Value::Array(ref scopes) => {
+                scopes.iter().filter_map(|s| s.as_str()).collect::<Vec<_>>().join(", ")
+            }
+            // a rule without a scope sets the defaults, in older themes
+            _ => {
+                let settings = &mut theme.settings;
+                settings.foreground = settings.foreground.or(style.foreground);
+                settings.background = settings.background.or(style.background);
+                continue;
+            }
+        };
+
+        theme::push_rule(&mut theme, &scope, style, path);
+    }
+
+    // paint's own chrome, see theme.rs
+    let chrome = [
+        (theme::HEADER, colors("editorGroupHeader.tabsBackground").or(colors("editorGroupHeader.noTabsBackground"))),
+        (theme::BORDER, colors("editorGroupHeader.tabsBorder").or(colors("editorGroup.border"))),
+        (theme::HIGHLIGHT, colors("editor.lineHighlightBackground")),
+        (theme::LINE_NUMBERS, colors("editorLineNumber.foreground")),
+    ];
+
+    for &(name, color) in &chrome {
+        if let Some(color) = color {
+            theme.scopes.push(theme::chrome_item(name, color));
+        }
+    }
+
+    Ok(theme)
+}
+
+
+fn style_modifier(settings: &Value) -> StyleModifier {
+    let color = |key: &str| settings[key].as_str().and_then(color::parse);
+
+    StyleModifier {
+        foreground: color("foreground"),
+        background: color("background"),
+        font_style: settings["fontStyle"].as_str().map(theme::parse_font_style),
+    }
+}
+
+
+// Reads a theme and whatever it includes, the including theme wins
+fn read_json(path: &Path, depth: usize) -> Result<Value, String> {
+    if depth > 10 {
+        return Err(format!("too many levels of `include` at '{}'", path.display()));
+    }
+
+    let data = theme::read(path)?;
+    let mut json: Value = serde_json::from_str(&theme::strip_comments(&data))
+        .map_err(|e| format!("problem parsing '{}': {}", path.display(), e))?;
+
+    // relative to the theme that names it, like `include`
+    let rules_path = json["tokenColors"].as_str().map(|file| path.parent().unwrap_or(Path::new("")).join(file));
+
+    if let Some(rules_path) = rules_path {
+        json["tokenColors"] = read_rules(&rules_path, depth)?;
+    }
+
+    let include = json["include"].as_str().map(String::from);
+
+    if let Some(include) = include {
+        let base_path = path.parent().unwrap_or(Path::new("")).join(include);
+        let mut base = read_json(&base_path, depth + 1)?;
+
+        // token rules are added to, later ones winning; colors are replaced
+        if let (Some(rules), Some(extra)) = (base["tokenColors"].as_array().cloned(), json["tokenColors"].as_array()) {
+            base["tokenColors"] = Value::Array(rules.into_iter().chain(extra.iter().cloned()).collect());
+        } else if json["tokenColors"].is_array() {
+            base["tokenColors"] = json["tokenColors"].clone();
+        }
+
+        if let Some(colors) = json["colors"].as_object() {
+            if !base["colors"].is_object() {
+                base["colors"] = Value::Object(Map::new());
+            }
+
+            for (key, value) in colors {
+                base["colors"][key] = value.clone();
+            }
+        }
+
+        for key in &["name", "author", "type"] {
+            if !json[*key].is_null() {
+                base[*key] = json[*key].clone();
+            }
+        }
+
+        json = base;
+    }
+
+    Ok(json)
+}
+
+
+// `tokenColors` from another file: a .json theme (or just its rule list), or
+// a .tmTheme, whose rules are turned into the same JSON shape
+fn read_rules(path: &Path, depth: usize) -> Result<Value, String> {
+    if path.extension().is_some_and(|e| e == "json") {
+        let json = read_json(path, depth + 1)?;
+        return Ok(if json.is_array() { json } else { json["tokenColors"].clone() });
+    }
+
+    let theme = ThemeSet::get_theme(path)
+        .map_err(|e| format!("problem loading '{}': {:?}", path.display(), e))?;
+
+    let object = |entries: Vec<(&str, String)>| {
+        Value::Object(entries.into_iter().map(|(k, v)| (String::from(k), Value::String(v))).collect())
+    };
+
+    // the file's default colors, as a rule without a scope
+    let mut defaults = Vec::new();
+    if let Some(fore) = theme.settings.foreground { defaults.push(("foreground", color::hex(&fore))); }
+    if let Some(back) = theme.settings.background { defaults.push(("background", color::hex(&back))); }
+
+    let mut rules = Vec::new();
+
+    if !defaults.is_empty() {
+        let mut rule = Map::new();
+        rule.insert(String::from("settings"), object(defaults));
+        rules.push(Value::Object(rule));
+    }
+
+    for item in &theme.scopes {
+        let (scope, settings) = theme::rule_entries(item);
+
+        let mut rule = Map::new();
+        rule.insert(String::from("scope"), Value::String(scope));
+        rule.insert(String::from("settings"), object(settings));
+        rules.push(Value::Object(rule));
+    }
+
+    Ok(Value::Array(rules))
+}
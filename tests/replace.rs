@@ -0,0 +1,111 @@
+// Replace mode puts the css for every block into one <style>, so two blocks
+// must never define the same selector differently.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+
+
+fn replace(name: &str, page: &str, args: &[&str]) -> String {
+    let path = env::temp_dir().join(format!("paint-test-{}.html", name));
+    fs::write(&path, page).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_paint"))
+        .arg("replace")
+        .arg(&path)
+        .args(args)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+
+// every rule's selector, with the @media it's in
+fn selectors(html: &str) -> Vec<String> {
+    let start = html.find("<style>").expect("no <style> in output") + "<style>".len();
+    let css = &html[start..html.find("</style>").unwrap()];
+
+    let mut found = Vec::new();
+    let mut open: Vec<String> = Vec::new();
+    let mut text = String::new();
+    let mut leaf = false;
+
+    for c in css.chars() {
+        match c {
+            '{' => {
+                open.push(text.split_whitespace().collect::<Vec<_>>().join(" "));
+                text.clear();
+                leaf = true;
+            }
+            '}' => {
+                if leaf { found.push(open.join(" { ")); }
+                open.pop();
+                text.clear();
+                leaf = false;
+            }
+            _ => text.push(c),
+        }
+    }
+
+    found
+}
+
+
+fn assert_no_duplicates(html: &str) {
+    let mut seen = Vec::new();
+
+    for selector in selectors(html) {
+        assert!(!seen.contains(&selector), "'{}' is defined twice", selector);
+        seen.push(selector);
+    }
+}
+
+
+const PAGE: &str = r#"<html><head><title>test</title></head><body>
+<pre data-paint="rs">fn main() { let x = "s"; // c
+}</pre>
+<pre data-paint="py">def f(x):
+    # c
+    return "s" + 1</pre>
+<pre data-paint="rs" data-css-used-only>fn g() {}</pre>
+</body></html>
+"#;
+
+
+#[test]
+fn same_theme_blocks() {
+    assert_no_duplicates(&replace("same-theme", PAGE, &[]));
+}
+
+
+#[test]
+fn same_theme_light_dark_blocks() {
+    let html = replace("light-dark", PAGE, &["--theme-dark", "oceanic next"]);
+    assert_no_duplicates(&html);
+}
+
+
+#[test]
+fn mixed_theme_blocks() {
+    let page = PAGE.replace(r#"data-paint="py""#, r#"data-paint="py" data-theme="monokai extended""#);
+    assert_no_duplicates(&replace("mixed-theme", &page, &["--theme-dark", "oceanic next"]));
+}
+
+
+#[test]
+fn same_settings_share_a_prefix() {
+    let html = replace("shared", PAGE, &["--theme-dark", "oceanic next", "--css-used-only"]);
+    assert_no_duplicates(&html);
+    assert!(!html.contains("paint-2"), "blocks with the same settings got different prefixes");
+}
+
+
+#[test]
+fn different_settings_get_their_own_prefix() {
+    let page = PAGE.replace(r#"data-paint="py""#, r#"data-paint="py" data-font-size="16px""#);
+    let html = replace("own-prefix", &page, &[]);
+    assert_no_duplicates(&html);
+    assert!(html.contains("class='paint-2'"));
+}